@@ -16,79 +16,364 @@ pub struct BridgeArgs {
     #[command(flatten)]
     pub session_args: CommonArgs,
 
-    #[arg(short, long, num_args = 0.., value_parser(parse_mavlink_connection_vec))]
-    pub mavlink_connections: Option<std::vec::Vec<MAVLinkConnection>>,
+    /// A MAVLink connection (repeat for several connections), e.g. `-E serial:/dev/ttyACM0:57600 -E udpin:0.0.0.0:14550`.
+    /// Options of the connection follow its endpoint, separated by commas:
+    ///   - `version=1|2`: only exchange frames of this MAVLink version
+    ///   - `filter=<id>|!<id>...`: only exchange these message ids, never the ones prefixed by `!`
+    ///   - `heartbeat=true`: send the bridge's HEARTBEAT through this connection
+    ///   - any other connection setting, e.g. `stream_rates.messages.33=4` or `replay.speed=2`
+    #[arg(
+        short = 'E',
+        long = "endpoint",
+        value_name = "ENDPOINT[,OPTION=VALUE...]",
+        verbatim_doc_comment
+    )]
+    pub mavlink_connections: Vec<MAVLinkConnection>,
 
     #[arg(short, long)]
     pub broadcast_channel_capacity: Option<usize>,
 
+    /// Publish the MAVLink frames read from the connections on zenoh.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub to_zenoh: Option<bool>,
+
+    /// Write the MAVLink frames received from zenoh to the connections.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub from_zenoh: Option<bool>,
+
+    /// Authorization of the frames and commands coming from zenoh, as JSON (e.g.
+    /// `{"systems":{"1":{"sources":["<zenoh_id>"],"commands":[400]}}}` to only accept frames to system 1 from
+    /// this zenoh id, and let it arm the vehicle).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub from_zenoh_access: Option<serde_json::Value>,
+
+    /// Also publish the messages read from the connections as JSON on zenoh.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub to_zenoh_json: Option<bool>,
+
+    /// Number of worker threads of the plugin's async runtime.
+    #[arg(long, value_name = "NUMBER")]
+    pub work_thread_num: Option<usize>,
+
+    /// Maximum number of blocking threads of the plugin's async runtime.
+    #[arg(long, value_name = "NUMBER")]
+    pub max_block_thread_num: Option<usize>,
+
+    /// Run the serial connections on a dedicated single-threaded runtime, as JSON (e.g. `{}`, or `{"cpu":2}` to pin
+    /// its thread to CPU 2).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub serial_runtime: Option<serde_json::Value>,
+
+    /// MAVLink system id of the bridge.
+    #[arg(long, value_name = "ID")]
+    pub system_id: Option<u8>,
+
+    /// MAVLink component id of the bridge.
+    #[arg(long, value_name = "ID")]
+    pub component_id: Option<u8>,
+
+    /// MAV_TYPE advertised in the bridge's HEARTBEAT.
+    #[arg(long, value_name = "MAV_TYPE")]
+    pub mav_type: Option<u8>,
+
+    /// Period (in seconds) of the bridge's HEARTBEAT.
+    #[arg(long, value_name = "FLOAT")]
+    pub heartbeat_interval: Option<f32>,
+
+    /// Period (in seconds) of the connection statistics (0 to disable them).
+    #[arg(long, value_name = "FLOAT")]
+    pub stats_interval: Option<f32>,
+
+    /// Telemetry rates requested from vehicles, by system id, as JSON (e.g. `{"1":{"messages":{"33":4}}}`).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub vehicle_stream_rates: Option<serde_json::Value>,
+
+    /// Liveliness monitoring and reopening of the connections, as JSON (e.g. `{"timeout":10,"reconnect_delay":2}`).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub health: Option<serde_json::Value>,
+
+    /// Recording of the MAVLink traffic, as JSON (e.g. `{"directory":"/var/log/mavlink"}`).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub recorder: Option<serde_json::Value>,
+
+    /// Number of samples kept for late joiners, by message id, as JSON (e.g. `{"0":1,"24":10}`).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub to_zenoh_history: Option<serde_json::Value>,
+
+    /// Priority, congestion control and express flag by message, as JSON (a list of rules).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub to_zenoh_qos: Option<serde_json::Value>,
+
+    /// Batching of the frames published on zenoh, as JSON (e.g. `{"window":0.01,"max_bytes":1024}`).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub to_zenoh_batch: Option<serde_json::Value>,
+
+    /// Compression of the payloads published on zenoh, as JSON (e.g. `{"algorithm":"zstd"}`).
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub to_zenoh_compression: Option<serde_json::Value>,
+
     // Configures HTTP interface for the REST API (disabled by default, setting this option enables it). Accepted values:
     ///  - a port number
     ///  - a string with format `<local_ip>:<port_number>` (to bind the HTTP server to a specific interface).
     #[arg(short, long, value_name = "PORT | IP:PORT", verbatim_doc_comment)]
     pub rest_http_port: Option<String>,
 
+    /// Serve the health of the MAVLink connections on `http://<IP:PORT>/healthz` (503 when unhealthy). Accepted values:
+    ///  - a port number
+    ///  - a string with format `<local_ip>:<port_number>` (to bind the HTTP server to a specific interface).
+    #[arg(long, value_name = "PORT | IP:PORT", verbatim_doc_comment)]
+    pub healthz: Option<String>,
+
+    /// Check the configuration (file and command line arguments) and print the effective one, then exit
+    /// (with a non-zero status if problems are found).
+    #[arg(long)]
+    pub check_config: bool,
+
     /// Experimental!! Run a watchdog thread that monitors the bridge's async executor and
     /// reports as error log any stalled status during the specified period [default: 1.0 second]
     #[arg(short, long, value_name = "FLOAT", default_missing_value = "1.0")]
     pub watchdog: Option<Option<f32>>,
 }
 
-fn parse_mavlink_connection_vec(val: &str) -> Result<Vec<MAVLinkConnection>, String> {
-    serde_json::from_str(val).map_err(|e| e.to_string())?
+fn parse_json(val: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(val).map_err(|e| e.to_string())
 }
 
-impl From<BridgeArgs> for Config {
-    fn from(value: BridgeArgs) -> Self {
-        (&value).into()
+impl TryFrom<BridgeArgs> for Config {
+    type Error = String;
+
+    fn try_from(value: BridgeArgs) -> Result<Self, Self::Error> {
+        (&value).try_into()
     }
 }
 
-impl From<&BridgeArgs> for Config {
-    fn from(args: &BridgeArgs) -> Self {
-        let mut config = (&args.session_args).into();
+impl TryFrom<&BridgeArgs> for Config {
+    type Error = String;
 
-        insert_json5_option(&mut config, "plugins/mavlink/broadcast_channel_capacity", &args.broadcast_channel_capacity);
-        // FIXME: fix settings. unable to pass vector via CLI
-        insert_json5_option(
+    fn try_from(args: &BridgeArgs) -> Result<Self, Self::Error> {
+        let mut config = Config::try_from(&args.session_args)?;
+        // report the problems of the file before the ones of the arguments
+        check_plugin_config(&config).map_err(|e| format!("plugins/mavlink: {e}"))?;
+        // the environment variables override the file, the arguments override both
+        crate::env::apply_env(&mut config)?;
+
+        insert_json5_list(
             &mut config,
             "plugins/mavlink/mavlink_connections",
             &args.mavlink_connections,
-        );
-  
-        insert_json5_option(&mut config, "plugins/rest/http_port", &args.rest_http_port);
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/broadcast_channel_capacity",
+            &args.broadcast_channel_capacity,
+        )?;
+        insert_json5_option(&mut config, "plugins/mavlink/to_zenoh", &args.to_zenoh)?;
+        insert_json5_option(&mut config, "plugins/mavlink/from_zenoh", &args.from_zenoh)?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/from_zenoh_access",
+            &args.from_zenoh_access,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/to_zenoh_json",
+            &args.to_zenoh_json,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/work_thread_num",
+            &args.work_thread_num,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/max_block_thread_num",
+            &args.max_block_thread_num,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/serial_runtime",
+            &args.serial_runtime,
+        )?;
+        insert_json5_option(&mut config, "plugins/mavlink/system_id", &args.system_id)?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/component_id",
+            &args.component_id,
+        )?;
+        insert_json5_option(&mut config, "plugins/mavlink/mav_type", &args.mav_type)?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/heartbeat_interval",
+            &args.heartbeat_interval,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/stats_interval",
+            &args.stats_interval,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/vehicle_stream_rates",
+            &args.vehicle_stream_rates,
+        )?;
+        insert_json5_option(&mut config, "plugins/mavlink/health", &args.health)?;
+        insert_json5_option(&mut config, "plugins/mavlink/recorder", &args.recorder)?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/to_zenoh_history",
+            &args.to_zenoh_history,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/to_zenoh_qos",
+            &args.to_zenoh_qos,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/to_zenoh_batch",
+            &args.to_zenoh_batch,
+        )?;
+        insert_json5_option(
+            &mut config,
+            "plugins/mavlink/to_zenoh_compression",
+            &args.to_zenoh_compression,
+        )?;
+
+        insert_json5_option(&mut config, "plugins/rest/http_port", &args.rest_http_port)?;
+
+        Ok(config)
+    }
+}
+
+/// Applies a `--cfg` KEY:VALUE pair, VALUE being JSON5.
+pub(crate) fn insert_cfg(config: &mut Config, cfg: &str) -> Result<(), String> {
+    let (key, value) = cfg
+        .split_once(':')
+        .ok_or_else(|| format!("--cfg {cfg}: expecting KEY:VALUE"))?;
+    insert_json5_str(config, key, value).map_err(|e| format!("--cfg {e}"))
+}
+
+/// Inserts `json5` at `key`, the error naming the key. Changes to the MAVLink plugin's section are validated
+/// right away, rather than when the plugin starts.
+fn insert_json5_str(config: &mut Config, key: &str, json5: &str) -> Result<(), String> {
+    config
+        .insert_json5(key, json5)
+        .map_err(|e| format!("{key}: {e}"))?;
+    if key.trim_start_matches('/').starts_with("plugins/mavlink") {
+        check_plugin_config(config).map_err(|e| format!("{key}: {e}"))?;
+    }
+    Ok(())
+}
 
-        config
+fn check_plugin_config(config: &Config) -> Result<(), String> {
+    match config.plugin("mavlink") {
+        Some(plugin) => {
+            serde_json::from_value::<zenoh_plugin_mavlink::config::Config>(plugin.clone())
+                .map(drop)
+                .map_err(|e| format!("invalid MAVLink plugin configuration: {e}"))
+        }
+        None => Ok(()),
     }
 }
 
-pub(crate) fn insert_json5<T>(config: &mut Config, key: &str, value: &T)
+pub(crate) fn insert_json5<T>(config: &mut Config, key: &str, value: &T) -> Result<(), String>
 where
     T: Sized + serde::Serialize,
 {
-    config
-        .insert_json5(key, &serde_json::to_string(value).unwrap())
-        .unwrap();
+    let json = serde_json::to_string(value).map_err(|e| format!("{key}: {e}"))?;
+    insert_json5_str(config, key, &json)
 }
 
-pub(crate) fn insert_json5_option<T>(config: &mut Config, key: &str, value: &Option<T>)
+pub(crate) fn insert_json5_option<T>(
+    config: &mut Config,
+    key: &str,
+    value: &Option<T>,
+) -> Result<(), String>
 where
     T: Sized + serde::Serialize,
 {
-    if let Some(v) = value {
-        config
-            .insert_json5(key, &serde_json::to_string(v).unwrap())
-            .unwrap();
+    match value {
+        Some(v) => insert_json5(config, key, v),
+        None => Ok(()),
     }
 }
 
-pub(crate) fn insert_json5_list<T>(config: &mut Config, key: &str, values: &Vec<T>)
+pub(crate) fn insert_json5_list<T>(
+    config: &mut Config,
+    key: &str,
+    values: &Vec<T>,
+) -> Result<(), String>
 where
     T: Sized + serde::Serialize,
 {
-    if !values.is_empty() {
-        config
-            .insert_json5(key, &serde_json::to_string(values).unwrap())
-            .unwrap();
+    if values.is_empty() {
+        return Ok(());
+    }
+    insert_json5(config, key, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+    use zenoh::config::Config;
+
+    use super::{insert_cfg, BridgeArgs};
+
+    fn config(args: &[&str]) -> Result<Config, String> {
+        let args = BridgeArgs::parse_from(["zenoh-bridge-mavlink"].iter().chain(args));
+        let mut config = Config::try_from(&args)?;
+        for cfg in &args.session_args.cfg {
+            insert_cfg(&mut config, cfg)?;
+        }
+        Ok(config)
+    }
+
+    fn get(config: &Config, key: &str) -> serde_json::Value {
+        serde_json::from_str(&config.get_json(key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn cfg() {
+        let config = config(&[
+            "--cfg",
+            "transport/unicast/max_links:2",
+            "--cfg",
+            "plugins/mavlink/to_zenoh_history:{24: 10}",
+        ])
+        .unwrap();
+        assert_eq!(get(&config, "transport/unicast/max_links"), 2);
+        assert_eq!(
+            get(&config, "plugins/mavlink/to_zenoh_history"),
+            json!({"24": 10})
+        );
+    }
+
+    #[test]
+    fn cfg_overrides_flags() {
+        let config = config(&[
+            "--to-zenoh",
+            "false",
+            "--cfg",
+            "plugins/mavlink/to_zenoh:true",
+        ])
+        .unwrap();
+        assert_eq!(get(&config, "plugins/mavlink/to_zenoh"), true);
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let e = config(&["--cfg", "plugins/mavlink/to_zenho:true"]).unwrap_err();
+        assert!(e.starts_with("--cfg plugins/mavlink/to_zenho:"), "{e}");
+        assert!(e.contains("to_zenho"), "{e}");
+
+        let e = config(&["--cfg", "plugins/mavlink/system_id:\"one\""]).unwrap_err();
+        assert!(e.starts_with("--cfg plugins/mavlink/system_id:"), "{e}");
+
+        let e = config(&["--cfg", "transport/unicast/max_links"]).unwrap_err();
+        assert!(e.contains("KEY:VALUE"), "{e}");
+
+        let e = config(&["--to-zenoh-batch", "{\"windows\":1}"]).unwrap_err();
+        assert!(e.starts_with("plugins/mavlink/to_zenoh_batch:"), "{e}");
     }
 }
@@ -0,0 +1,152 @@
+//! Configuration of the MAVLink plugin through `ZENOH_BRIDGE_MAVLINK_*` environment variables, for container
+//! deployments.
+//!
+//! - `ZENOH_BRIDGE_MAVLINK_<SETTING>` sets a setting of the plugin, e.g. `ZENOH_BRIDGE_MAVLINK_TO_ZENOH=true`.
+//!   Nested settings are separated by `__`, e.g. `ZENOH_BRIDGE_MAVLINK_TO_ZENOH_BATCH__WINDOW=0.05`. Values are
+//!   JSON, or a string if they do not parse as JSON.
+//! - `ZENOH_BRIDGE_MAVLINK_ENDPOINTS` sets the MAVLink connections, separated by `;`, each one with the syntax of
+//!   `-E` (e.g. `serial:/dev/ttyACM0:57600,heartbeat=true;udpin:0.0.0.0:14550,filter=0|24`).
+//!
+//! They override the configuration file, and are overridden by the command line arguments.
+
+use serde_json::{Map, Value};
+use zenoh::config::Config;
+use zenoh_plugin_mavlink::mavlink_connection::MAVLinkConnection;
+
+use crate::bridge_args::insert_json5;
+
+pub const ENV_PREFIX: &str = "ZENOH_BRIDGE_MAVLINK_";
+pub const ENV_ENDPOINTS: &str = "ENDPOINTS";
+
+/// Applies the `ZENOH_BRIDGE_MAVLINK_*` environment variables to `config`.
+pub fn apply_env(config: &mut Config) -> Result<(), String> {
+    apply_vars(config, std::env::vars())
+}
+
+fn apply_vars(
+    config: &mut Config,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<(), String> {
+    // sorted, so that a setting is applied before the settings nested in it
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+
+    for (name, value) in vars {
+        let setting = &name[ENV_PREFIX.len()..];
+        let mut section = config
+            .plugin("mavlink")
+            .cloned()
+            .unwrap_or_else(|| Value::Object(Map::new()));
+        if setting == ENV_ENDPOINTS {
+            let connections = value
+                .split(';')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::parse::<MAVLinkConnection>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("{name}: {e}"))?;
+            section["mavlink_connections"] =
+                serde_json::to_value(connections).map_err(|e| format!("{name}: {e}"))?;
+        } else {
+            let path: Vec<String> = setting.split("__").map(str::to_lowercase).collect();
+            if path.iter().any(String::is_empty) {
+                return Err(format!("{name}: invalid setting name"));
+            }
+            let value =
+                serde_json::from_str(&value).unwrap_or_else(|_| Value::String(value.clone()));
+            set(&mut section, &path, value);
+        }
+        insert_json5(config, "plugins/mavlink", &section).map_err(|e| format!("{name}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Sets the value at `path` in `section`, creating the objects on the way.
+fn set(section: &mut Value, path: &[String], value: Value) {
+    let mut current = section;
+    for key in path {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        current = current
+            .as_object_mut()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert(Value::Null);
+    }
+    *current = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use zenoh::config::Config;
+
+    use super::apply_vars;
+
+    fn config(vars: &[(&str, &str)]) -> Result<serde_json::Value, String> {
+        let mut config = Config::default();
+        config
+            .insert_json5("plugins/mavlink", r#"{ to_zenoh: false, system_id: 200 }"#)
+            .unwrap();
+        apply_vars(
+            &mut config,
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+        )?;
+        Ok(config.plugin("mavlink").cloned().unwrap())
+    }
+
+    #[test]
+    fn settings() {
+        let plugin = config(&[
+            ("ZENOH_BRIDGE_MAVLINK_TO_ZENOH", "true"),
+            ("ZENOH_BRIDGE_MAVLINK_TO_ZENOH_BATCH__WINDOW", "0.05"),
+            (
+                "ZENOH_BRIDGE_MAVLINK_RECORDER__DIRECTORY",
+                "/var/log/mavlink",
+            ),
+            (
+                "ZENOH_BRIDGE_MAVLINK_VEHICLE_STREAM_RATES__1__MESSAGES__33",
+                "4",
+            ),
+            ("OTHER_VARIABLE", "ignored"),
+        ])
+        .unwrap();
+        assert_eq!(plugin["to_zenoh"], true);
+        assert_eq!(plugin["system_id"], 200);
+        assert_eq!(plugin["to_zenoh_batch"], json!({"window": 0.05}));
+        assert_eq!(plugin["recorder"]["directory"], "/var/log/mavlink");
+        assert_eq!(plugin["vehicle_stream_rates"]["1"]["messages"]["33"], 4);
+    }
+
+    #[test]
+    fn endpoints() {
+        let plugin = config(&[(
+            "ZENOH_BRIDGE_MAVLINK_ENDPOINTS",
+            "serial:/dev/ttyACM0:57600,heartbeat=true; udpin:0.0.0.0:14550,filter=0|24",
+        )])
+        .unwrap();
+        let connections = plugin["mavlink_connections"].as_array().unwrap();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0]["endpoint"], "serial:/dev/ttyACM0:57600");
+        assert_eq!(connections[0]["heartbeat"], true);
+        assert_eq!(connections[1]["endpoint"], "udpin:0.0.0.0:14550");
+    }
+
+    #[test]
+    fn errors_name_the_variable() {
+        let e = config(&[("ZENOH_BRIDGE_MAVLINK_TO_ZENHO", "true")]).unwrap_err();
+        assert!(e.starts_with("ZENOH_BRIDGE_MAVLINK_TO_ZENHO:"), "{e}");
+        let e = config(&[("ZENOH_BRIDGE_MAVLINK_SYSTEM_ID", "one")]).unwrap_err();
+        assert!(e.starts_with("ZENOH_BRIDGE_MAVLINK_SYSTEM_ID:"), "{e}");
+        let e = config(&[(
+            "ZENOH_BRIDGE_MAVLINK_ENDPOINTS",
+            "udpin:0.0.0.0:14550,bogus=1",
+        )])
+        .unwrap_err();
+        assert!(e.starts_with("ZENOH_BRIDGE_MAVLINK_ENDPOINTS:"), "{e}");
+    }
+}
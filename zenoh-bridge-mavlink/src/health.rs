@@ -0,0 +1,206 @@
+//! Health of the bridge, for orchestrators and service managers.
+//!
+//! - With `--healthz`, `GET /healthz` replies `200 OK` with the health status of the MAVLink connections as JSON
+//!   (see [`zenoh_plugin_mavlink::health`]) if they are all healthy, `503 Service Unavailable` otherwise.
+//! - Under systemd (`NOTIFY_SOCKET` set, `Type=notify`), the bridge notifies `READY=1` once started and, if the
+//!   unit has a `WatchdogSec`, `WATCHDOG=1` every half period as long as the bridge's and the plugin's runtimes
+//!   answer. A hung bridge is then restarted by systemd.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use zenoh::{internal::runtime::Runtime, key_expr::format::keformat, Session};
+use zenoh_plugin_mavlink::liveliness::ke_liveliness_health;
+
+/// Time given to the plugin to reply its health status.
+pub const HEALTH_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+pub const HEALTHZ_PATH: &str = "/healthz";
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+/// Maximum size of the HTTP requests read.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Serves `/healthz` on `healthz` (`<port>` or `<ip>:<port>`) if set, and notifies systemd if the bridge runs as a
+/// notify service.
+pub async fn start(runtime: &Runtime, healthz: Option<String>) {
+    let systemd = std::env::var_os(NOTIFY_SOCKET).is_some();
+    if healthz.is_none() && !systemd {
+        return;
+    }
+    let zsession = match zenoh::session::init(runtime.clone()).await {
+        Ok(session) => Arc::new(session),
+        Err(e) => {
+            tracing::error!("Unable to init zenoh session for the health checks: {e}");
+            return;
+        }
+    };
+
+    if let Some(address) = healthz {
+        tokio::spawn(serve_healthz(address, zsession.clone()));
+    }
+    if systemd {
+        match sd_notify("READY=1") {
+            Ok(()) => tracing::info!("notified systemd that the bridge is ready"),
+            Err(e) => tracing::warn!("unable to notify systemd: {e}"),
+        }
+        if let Some(period) = watchdog_period() {
+            tokio::spawn(run_systemd_watchdog(period, zsession));
+        }
+    }
+}
+
+/// Queries the health status of the MAVLink plugin.
+pub async fn plugin_health(zsession: &Session) -> Result<serde_json::Value, String> {
+    let ke = keformat!(
+        ke_liveliness_health::formatter(),
+        zenoh_id = zsession.zid().into_keyexpr()
+    )
+    .unwrap();
+    let replies = zsession
+        .get(ke)
+        .timeout(HEALTH_QUERY_TIMEOUT)
+        .await
+        .map_err(|e| e.to_string())?;
+    let reply = replies
+        .recv_async()
+        .await
+        .map_err(|_| "no health status from the MAVLink plugin".to_string())?;
+    let sample = reply
+        .result()
+        .map_err(|e| format!("health query failed: {e:?}"))?;
+    serde_json::from_slice(&sample.payload().to_bytes()).map_err(|e| e.to_string())
+}
+
+async fn serve_healthz(address: String, zsession: Arc<Session>) {
+    // a port number alone listens on every interface, as for the REST API
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("0.0.0.0:{port}"),
+        Err(_) => address,
+    };
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Unable to serve {HEALTHZ_PATH} on {address}: {e}");
+            return;
+        }
+    };
+    tracing::info!("serving health checks on http://{address}{HEALTHZ_PATH}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let zsession = zsession.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_healthz(stream, &zsession).await {
+                        tracing::debug!("health check request failed: {e}");
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("failed to accept health check connection: {e}"),
+        }
+    }
+}
+
+async fn handle_healthz(mut stream: TcpStream, zsession: &Session) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+    let (status, body) = if path != HEALTHZ_PATH {
+        ("404 Not Found", String::new())
+    } else if method != "GET" && method != "HEAD" {
+        ("405 Method Not Allowed", String::new())
+    } else {
+        match plugin_health(zsession).await {
+            Ok(health) if health["healthy"] == true => ("200 OK", health.to_string()),
+            Ok(health) => ("503 Service Unavailable", health.to_string()),
+            Err(e) => (
+                "503 Service Unavailable",
+                serde_json::json!({ "healthy": false, "error": e }).to_string(),
+            ),
+        }
+    };
+    let body = if method == "HEAD" { "" } else { &body };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Notifies systemd as long as the plugin answers, so that a hung bridge (or plugin) is restarted.
+async fn run_systemd_watchdog(period: Duration, zsession: Arc<Session>) {
+    tracing::info!(
+        "notifying the systemd watchdog every {} seconds",
+        (period / 2).as_secs_f32()
+    );
+    let mut interval = tokio::time::interval(period / 2);
+    loop {
+        interval.tick().await;
+        match plugin_health(&zsession).await {
+            Ok(_) => {
+                if let Err(e) = sd_notify("WATCHDOG=1") {
+                    tracing::warn!("unable to notify the systemd watchdog: {e}");
+                }
+            }
+            Err(e) => tracing::warn!("not notifying the systemd watchdog: {e}"),
+        }
+    }
+}
+
+/// The systemd watchdog period, if enabled for this process.
+fn watchdog_period() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then_some(Duration::from_micros(usec))
+}
+
+/// Sends `state` to systemd (see `sd_notify(3)`).
+#[cfg(unix)]
+fn sd_notify(state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let Some(path) = std::env::var_os(NOTIFY_SOCKET) else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(std::io::Error::other(
+                "abstract notification sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sd_notify(_state: &str) -> std::io::Result<()> {
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use std::time::{Duration, SystemTime};
 
 use async_liveliness_monitor::LivelinessMonitor;
-use bridge_args::BridgeArgs;
+use bridge_args::{insert_cfg, BridgeArgs};
 use clap::Parser;
+use mavlink_args::MAVLinkArgs;
 use zenoh::{
     config::{self, Config},
     internal::{plugins::PluginsManager, runtime::RuntimeBuilder},
@@ -12,11 +13,18 @@ use zenoh_plugin_trait::Plugin;
 
 mod zenoh_args;
 mod bridge_args;
+mod env;
+mod health;
+mod mavlink_args;
+mod reload;
 
 const MAVLINK_ARG_START_FLAG: &str = "--mavlink-args";
 const MAVLINK_ARG_END_FLAG: &str = "--";
+/// Name of the threads of the bridge's runtime (the MAVLink plugin has its own, see
+/// [`zenoh_plugin_mavlink::runtime`]).
+const WORKER_THREAD_NAME: &str = "bridge-worker";
 
-fn parse_args() -> (Option<f32>, Config) {
+fn parse_args() -> (BridgeArgs, MAVLinkArgs, Config) {
     let mut mavlink_args = vec!["mavlink-args".to_string()];
     let mut user_args = Vec::new();
     let mut in_mavlink_args_section = false;
@@ -31,8 +39,14 @@ fn parse_args() -> (Option<f32>, Config) {
 
     // Create config parsing user-defined args
     let bridge_args = BridgeArgs::parse_from(user_args);
-    let watchdog_opt = bridge_args.watchdog.flatten();
-    let mut config: Config = bridge_args.into();
+    let mavlink_args = MAVLinkArgs::parse_from(mavlink_args);
+    let mut config = match load_config(&bridge_args, &mavlink_args) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{e}. Exiting...");
+            std::process::exit(-1);
+        }
+    };
 
     // Always add timestamps to publications (required for PublicationCache used in case of TRANSIENT_LOCAL topics)
     config
@@ -45,23 +59,46 @@ fn parse_args() -> (Option<f32>, Config) {
     // Enable loading plugins
     config.plugins_loading.set_enabled(true).unwrap();
 
-    (watchdog_opt, config)
+    (bridge_args, mavlink_args, config)
+}
+
+/// Builds the configuration from the configuration file and the command line arguments.
+fn load_config(bridge_args: &BridgeArgs, mavlink_args: &MAVLinkArgs) -> Result<Config, String> {
+    let mut config = Config::try_from(bridge_args)?;
+
+    // Append the MAVProxy-compatible arguments
+    mavlink_args
+        .apply(&mut config)
+        .map_err(|e| format!("Invalid --mavlink-args: {e}"))?;
+
+    // --cfg overrides everything else
+    for cfg in &bridge_args.session_args.cfg {
+        insert_cfg(&mut config, cfg)?;
+    }
+    Ok(config)
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name(WORKER_THREAD_NAME)
+        .enable_all()
+        .build()
+        .expect("Unable to create runtime");
+    runtime.block_on(run());
 }
 
-#[tokio::main]
-async fn main() {
+async fn run() {
     zenoh::init_log_from_env_or("z=info");
     tracing::info!(
         "zenoh-bridge-mavlink {}",
         zenoh_plugin_mavlink::MAVLinkPlugin::PLUGIN_LONG_VERSION
     );
 
-    let (watchdog_period, config) = parse_args();
-    tracing::info!("Zenoh {config:?}");
-
-    if let Some(period) = watchdog_period {
-        run_watchdog(period);
+    let (bridge_args, mavlink_args, config) = parse_args();
+    if bridge_args.check_config {
+        check_config(&config);
     }
+    tracing::info!("Zenoh {config:?}");
 
     let mut plugins_mgr = PluginsManager::static_plugins_only();
 
@@ -90,9 +127,60 @@ async fn main() {
         std::process::exit(-1);
     }
 
+    // report the bridge's health to orchestrators and systemd
+    health::start(&runtime, bridge_args.healthz.clone()).await;
+
+    // started once the plugin's runtime has its thread settings
+    if let Some(period) = bridge_args.watchdog.flatten() {
+        run_watchdog(period);
+    }
+
+    // reload the MAVLink plugin configuration when the configuration file changes
+    if let Some(path) = bridge_args.session_args.config.clone() {
+        tokio::spawn(reload::watch_config(
+            path,
+            bridge_args,
+            mavlink_args,
+            runtime.clone(),
+        ));
+    }
+
     futures::future::pending::<()>().await;
 }
 
+/// Prints the effective configuration and the problems of the MAVLink plugin's one, then exits.
+fn check_config(config: &Config) -> ! {
+    match serde_json::from_str::<serde_json::Value>(&config.to_string())
+        .and_then(|json| serde_json::to_string_pretty(&json))
+    {
+        Ok(json) => println!("{json}"),
+        Err(e) => println!("{config}\n(unable to pretty print the configuration: {e})"),
+    }
+
+    let plugin_config = config
+        .plugin("mavlink")
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({}));
+    let problems =
+        match serde_json::from_value::<zenoh_plugin_mavlink::config::Config>(plugin_config) {
+            Ok(plugin_config) => {
+                let mut problems = plugin_config.check();
+                problems.extend(plugin_config.check_environment());
+                problems
+            }
+            Err(e) => vec![format!("invalid plugins/mavlink configuration: {e}")],
+        };
+    if problems.is_empty() {
+        eprintln!("Configuration OK");
+        std::process::exit(0);
+    }
+    for problem in &problems {
+        eprintln!("error: {problem}");
+    }
+    eprintln!("{} problem(s) found in the configuration", problems.len());
+    std::process::exit(1);
+}
+
 fn run_watchdog(period: f32) {
     let sleep_time = Duration::from_secs_f32(period);
     // max delta accepted for watchdog thread sleep period
@@ -108,9 +196,18 @@ fn run_watchdog(period: f32) {
         report_threshold_2.as_secs_f32()
     );
 
-    // Start a Liveliness Monitor thread for tokio Runtime
-    let (_task, monitor) = LivelinessMonitor::start(tokio::task::spawn);
-    std::thread::spawn(move || {
+    // Start a Liveliness Monitor for the bridge's and the plugin's tokio Runtimes, by thread name
+    let (_task, bridge_monitor) = LivelinessMonitor::start(tokio::task::spawn);
+    let (_task, plugin_monitor) = LivelinessMonitor::start(zenoh_plugin_mavlink::spawn_runtime);
+    let monitors = [
+        (WORKER_THREAD_NAME, bridge_monitor),
+        (
+            zenoh_plugin_mavlink::runtime::WORKER_THREAD_NAME,
+            plugin_monitor,
+        ),
+    ];
+    let watchdog = std::thread::Builder::new().name("bridge-watchdog".to_string());
+    let spawned = watchdog.spawn(move || {
         tracing::debug!(
             "Watchdog started with period {} sec",
             sleep_time.as_secs_f32()
@@ -127,26 +224,31 @@ fn run_watchdog(period: f32) {
                     elapsed.as_secs_f32()
                 );
             }
-            // check last LivelinessMonitor's report
-            let report = monitor.latest_report();
-            if report.elapsed() > report_threshold_1 {
-                if report.elapsed() > sleep_time {
-                    tracing::error!(
-                        "Watchdog detecting tokio is stalled! No task scheduling since {} seconds",
-                        report.elapsed().as_secs_f32()
-                    );
-                } else if report.elapsed() > report_threshold_2 {
-                    tracing::warn!(
-                        "Watchdog detecting tokio was not scheduling tasks during the last {} ms",
-                        report.elapsed().as_micros()
-                    );
-                } else {
-                    tracing::info!(
-                        "Watchdog detecting tokio was not scheduling tasks during the last {} ms",
-                        report.elapsed().as_micros()
-                    );
+            // check last LivelinessMonitor's reports
+            for (threads, monitor) in &monitors {
+                let report = monitor.latest_report();
+                if report.elapsed() > report_threshold_1 {
+                    if report.elapsed() > sleep_time {
+                        tracing::error!(
+                            "Watchdog detecting tokio ({threads} threads) is stalled! No task scheduling since {} seconds",
+                            report.elapsed().as_secs_f32()
+                        );
+                    } else if report.elapsed() > report_threshold_2 {
+                        tracing::warn!(
+                            "Watchdog detecting tokio ({threads} threads) was not scheduling tasks during the last {} ms",
+                            report.elapsed().as_micros()
+                        );
+                    } else {
+                        tracing::info!(
+                            "Watchdog detecting tokio ({threads} threads) was not scheduling tasks during the last {} ms",
+                            report.elapsed().as_micros()
+                        );
+                    }
                 }
             }
         }
     });
+    if let Err(e) = spawned {
+        tracing::error!("Unable to start the watchdog thread: {e}");
+    }
 }
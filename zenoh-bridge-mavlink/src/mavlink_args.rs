@@ -0,0 +1,278 @@
+//! MAVProxy-compatible arguments, passed between `--mavlink-args` and `--`, e.g.:
+//! `zenoh-bridge-mavlink --mavlink-args --master /dev/ttyACM0 --baudrate 115200 --out udp:10.0.0.2:14550 --`
+//!
+//! They are translated into MAVLink connections, appended to the ones of the configuration file and of `-E`.
+
+use zenoh::config::Config;
+use zenoh_plugin_mavlink::mavlink_connection::MAVLinkConnection;
+
+use crate::bridge_args::{insert_json5, insert_json5_option};
+
+pub const DEFAULT_BAUDRATE: u32 = 57600;
+
+#[derive(clap::Parser, Clone, Debug)]
+#[command(about = "MAVProxy-compatible arguments")]
+pub struct MAVLinkArgs {
+    /// MAVLink master port (repeat for several ports), e.g. `/dev/ttyACM0`, `/dev/ttyUSB0,115200`, `udp:0.0.0.0:14550`
+    /// (listening), `udpout:10.0.0.2:14550` or `tcp:127.0.0.1:5760`. The bridge sends its HEARTBEAT to masters.
+    #[arg(long, value_name = "DEVICE")]
+    pub master: Vec<String>,
+
+    /// MAVLink output (repeat for several outputs), e.g. `udp:10.0.0.2:14550` (sending), `udpin:0.0.0.0:14550`,
+    /// `udpbcast:192.168.1.255:14550`, `tcpin:0.0.0.0:5760` or `/dev/ttyUSB1,57600`.
+    #[arg(long, value_name = "DEVICE")]
+    pub out: Vec<String>,
+
+    /// Baud rate of the serial ports without one.
+    #[arg(long, value_name = "BAUD", default_value_t = DEFAULT_BAUDRATE)]
+    pub baudrate: u32,
+
+    /// Only exchange MAVLink 1 frames on the masters and outputs.
+    #[arg(long)]
+    pub mav10: bool,
+
+    /// MAVLink system id of the bridge.
+    #[arg(long, value_name = "ID")]
+    pub source_system: Option<u8>,
+
+    /// MAVLink component id of the bridge.
+    #[arg(long, value_name = "ID")]
+    pub source_component: Option<u8>,
+}
+
+impl MAVLinkArgs {
+    /// Appends the connections to the ones of `config`, and sets the bridge's identity.
+    pub fn apply(&self, config: &mut Config) -> Result<(), String> {
+        let mut connections: Vec<MAVLinkConnection> = match config
+            .plugin("mavlink")
+            .and_then(|plugin| plugin.get("mavlink_connections"))
+        {
+            Some(connections) => serde_json::from_value(connections.clone())
+                .map_err(|e| format!("invalid mavlink_connections: {e}"))?,
+            None => Vec::new(),
+        };
+        let masters = self.master.iter().map(|master| (master, true));
+        let outs = self.out.iter().map(|out| (out, false));
+        for (device, is_master) in masters.chain(outs) {
+            let mut spec = self.endpoint(device, is_master)?;
+            if is_master {
+                spec.push_str(",heartbeat=true");
+            }
+            if self.mav10 {
+                spec.push_str(",version=1");
+            }
+            connections.push(spec.parse()?);
+        }
+
+        if !connections.is_empty() {
+            insert_json5(config, "plugins/mavlink/mavlink_connections", &connections)?;
+        }
+        insert_json5_option(config, "plugins/mavlink/system_id", &self.source_system)?;
+        insert_json5_option(
+            config,
+            "plugins/mavlink/component_id",
+            &self.source_component,
+        )?;
+        Ok(())
+    }
+
+    /// Translates a MAVProxy device into a MAVLink endpoint. As in MAVProxy, `udp:` listens on a master and
+    /// sends on an output.
+    fn endpoint(&self, device: &str, is_master: bool) -> Result<String, String> {
+        if let Some((protocol, address)) = device.split_once(':') {
+            let protocol = match protocol {
+                "udp" if is_master => Some("udpin"),
+                "udp" => Some("udpout"),
+                "tcp" => Some("tcpout"),
+                "udpin" | "udpout" | "udpbcast" | "tcpin" => Some(protocol),
+                _ => None,
+            };
+            if let Some(protocol) = protocol {
+                return Ok(format!("{protocol}:{address}"));
+            }
+        }
+
+        let (path, baudrate) = match device.split_once(',') {
+            Some((path, baudrate)) => (
+                path,
+                baudrate
+                    .parse()
+                    .map_err(|e| format!("invalid baud rate in `{device}`: {e}"))?,
+            ),
+            None => (device, self.baudrate),
+        };
+        if path.is_empty() {
+            return Err(format!("invalid device `{device}`"));
+        }
+        Ok(format!("serial:{path}:{baudrate}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use zenoh::config::Config;
+    use zenoh_plugin_mavlink::mavlink_connection::MAVLinkConnection;
+
+    use super::MAVLinkArgs;
+    use crate::bridge_args::BridgeArgs;
+
+    /// The configuration built from the bridge arguments, then the MAVProxy-compatible ones.
+    fn config(bridge_args: &[&str], mavlink_args: &[&str]) -> Result<Config, String> {
+        let bridge_args =
+            BridgeArgs::parse_from(["zenoh-bridge-mavlink"].iter().chain(bridge_args));
+        let mut config = Config::try_from(&bridge_args)?;
+        MAVLinkArgs::parse_from(["mavlink-args"].iter().chain(mavlink_args)).apply(&mut config)?;
+        Ok(config)
+    }
+
+    fn get(config: &Config, key: &str) -> serde_json::Value {
+        serde_json::from_str(&config.get_json(key).unwrap()).unwrap()
+    }
+
+    /// (endpoint, heartbeat, mavlink_version) of the connections.
+    fn connections(config: &Config) -> Vec<(String, bool, Option<u8>)> {
+        let connections: Vec<MAVLinkConnection> =
+            serde_json::from_value(get(config, "plugins/mavlink/mavlink_connections")).unwrap();
+        connections
+            .into_iter()
+            .map(|c| (c.endpoint, c.heartbeat, c.mavlink_version))
+            .collect()
+    }
+
+    #[test]
+    fn master() {
+        let config = config(
+            &[],
+            &[
+                "--master",
+                "udp:0.0.0.0:14550",
+                "--master",
+                "tcp:127.0.0.1:5760",
+                "--master",
+                "udpout:10.0.0.2:14550",
+                "--master",
+                "/dev/ttyACM0",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            connections(&config),
+            [
+                ("udpin:0.0.0.0:14550".to_string(), true, None),
+                ("tcpout:127.0.0.1:5760".to_string(), true, None),
+                ("udpout:10.0.0.2:14550".to_string(), true, None),
+                ("serial:/dev/ttyACM0:57600".to_string(), true, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn out() {
+        let config = config(
+            &[],
+            &[
+                "--out",
+                "udp:10.0.0.2:14550",
+                "--out",
+                "tcpin:0.0.0.0:5760",
+                "--out",
+                "udpbcast:192.168.1.255:14550",
+                "--out",
+                "/dev/ttyUSB1,115200",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            connections(&config),
+            [
+                ("udpout:10.0.0.2:14550".to_string(), false, None),
+                ("tcpin:0.0.0.0:5760".to_string(), false, None),
+                ("udpbcast:192.168.1.255:14550".to_string(), false, None),
+                ("serial:/dev/ttyUSB1:115200".to_string(), false, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn baudrate() {
+        let config = config(
+            &[],
+            &[
+                "--baudrate",
+                "115200",
+                "--master",
+                "/dev/ttyACM0",
+                "--out",
+                "/dev/ttyUSB1,9600",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            connections(&config),
+            [
+                ("serial:/dev/ttyACM0:115200".to_string(), true, None),
+                ("serial:/dev/ttyUSB1:9600".to_string(), false, None),
+            ]
+        );
+
+        let e = config(&[], &["--master", "/dev/ttyACM0,fast"]).unwrap_err();
+        assert!(e.contains("invalid baud rate"), "{e}");
+        let e = config(&[], &["--out", ",57600"]).unwrap_err();
+        assert!(e.contains("invalid device"), "{e}");
+    }
+
+    #[test]
+    fn mav10() {
+        let config = config(
+            &[],
+            &[
+                "--mav10",
+                "--master",
+                "/dev/ttyACM0",
+                "--out",
+                "udp:10.0.0.2:14550",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            connections(&config),
+            [
+                ("serial:/dev/ttyACM0:57600".to_string(), true, Some(1)),
+                ("udpout:10.0.0.2:14550".to_string(), false, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn source_system_and_component() {
+        let config = config(&[], &["--source-system", "1", "--source-component", "191"]).unwrap();
+        assert_eq!(get(&config, "plugins/mavlink/system_id"), 1);
+        assert_eq!(get(&config, "plugins/mavlink/component_id"), 191);
+    }
+
+    #[test]
+    fn precedence() {
+        // connections are appended to the ones of -E, the identity overrides the one of the bridge arguments
+        let config = config(
+            &["-E", "udpin:0.0.0.0:14551", "--system-id", "2"],
+            &["--master", "/dev/ttyACM0", "--source-system", "3"],
+        )
+        .unwrap();
+        assert_eq!(
+            connections(&config),
+            [
+                ("udpin:0.0.0.0:14551".to_string(), false, None),
+                ("serial:/dev/ttyACM0:57600".to_string(), true, None),
+            ]
+        );
+        assert_eq!(get(&config, "plugins/mavlink/system_id"), 3);
+
+        // without MAVProxy-compatible arguments, -E is kept as is
+        let config = config(&["-E", "udpin:0.0.0.0:14551"], &[]).unwrap();
+        assert_eq!(
+            connections(&config),
+            [("udpin:0.0.0.0:14551".to_string(), false, None)]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Hot reload of the MAVLink plugin configuration.
+//!
+//! When the configuration file changes (checked every [`POLL_PERIOD`]) or on `SIGHUP`, the configuration is built
+//! again from the file and the command line arguments, and its `plugins/mavlink` section is applied to the running
+//! plugin. Connections that did not change are left open.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::mpsc::unbounded_channel;
+use zenoh::internal::runtime::Runtime;
+
+use crate::{bridge_args::BridgeArgs, load_config, mavlink_args::MAVLinkArgs};
+
+pub const POLL_PERIOD: Duration = Duration::from_secs(1);
+
+pub async fn watch_config(
+    path: String,
+    bridge_args: BridgeArgs,
+    mavlink_args: MAVLinkArgs,
+    runtime: Runtime,
+) {
+    let path = PathBuf::from(path);
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    let (hangups_tx, mut hangups) = unbounded_channel();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                tokio::spawn(async move {
+                    while hangup.recv().await.is_some() && hangups_tx.send(()).is_ok() {}
+                });
+            }
+            Err(e) => tracing::warn!("unable to handle SIGHUP: {e}"),
+        }
+    }
+    #[cfg(not(unix))]
+    drop(hangups_tx);
+
+    tracing::info!("watching {} for configuration changes", path.display());
+    let mut last_modified: Option<SystemTime> = modified(&path);
+    let mut interval = tokio::time::interval(POLL_PERIOD);
+    loop {
+        tokio::select! {
+            Some(()) = hangups.recv() => tracing::info!("SIGHUP received, reloading configuration"),
+            _ = interval.tick() => {
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                tracing::info!("{} changed, reloading configuration", path.display());
+            }
+        }
+        if let Err(e) = reload(&bridge_args, &mavlink_args, &runtime) {
+            tracing::error!("configuration not reloaded: {e}");
+        }
+    }
+}
+
+/// Applies the `plugins/mavlink` section of the configuration to the running plugin, if it changed.
+fn reload(
+    bridge_args: &BridgeArgs,
+    mavlink_args: &MAVLinkArgs,
+    runtime: &Runtime,
+) -> Result<(), String> {
+    let config = load_config(bridge_args, mavlink_args)?;
+
+    let new = config.plugin("mavlink").cloned().unwrap_or_default();
+    let current = runtime.config().lock().plugin("mavlink").cloned();
+    if current.as_ref() == Some(&new) {
+        tracing::info!("MAVLink plugin configuration unchanged");
+        return Ok(());
+    }
+    runtime
+        .config()
+        .insert_json5("plugins/mavlink", &new.to_string())
+        .map_err(|e| e.to_string())?;
+    tracing::info!("MAVLink plugin configuration reloaded");
+    Ok(())
+}
@@ -17,6 +17,8 @@
 
 use zenoh::config::Config;
 
+use crate::bridge_args::{insert_json5, insert_json5_option};
+
 #[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Wai {
     Peer,
@@ -42,52 +44,105 @@ impl From<Wai> for zenoh::config::WhatAmI {
 
 #[derive(clap::Parser, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct CommonArgs {
-    #[arg(short, long)]
     /// A configuration file.
+    #[arg(short, long, value_name = "FILE")]
     pub config: Option<String>,
-    #[arg(short, long)]
     /// The Zenoh identifier (as an hexadecimal string, in lowercase - e.g.: a0b23...) that this bridge must use. If not set, a random unsigned 128bit integer will be used. Leading zeros are not accepted.
     /// WARNING: this id must be unique in the system and must be 32 chars maximum (128 bits)!
-    #[arg(short, long)]
-    id: Option<String>,
+    #[arg(short, long, value_name = "HEX_STRING")]
+    pub id: Option<String>,
     /// The Zenoh session mode [default: router].
+    #[arg(short, long)]
     pub mode: Option<Wai>,
-    #[arg(short = 'e', long)]
     /// Endpoints to connect to.
+    #[arg(short = 'e', long, value_name = "ENDPOINT")]
     pub connect: Vec<String>,
-    #[arg(short, long)]
     /// Endpoints to listen on.
+    #[arg(short, long, value_name = "ENDPOINT")]
     pub listen: Vec<String>,
-    #[arg(long)]
     /// Disable the multicast-based scouting mechanism.
+    #[arg(long)]
     pub no_multicast_scouting: bool,
+    /// Enable the shared-memory transport (requires the bridge to be built with the `shared-memory` feature).
     #[arg(long)]
-    /// Disable the multicast-based scouting mechanism.
     pub enable_shm: bool,
+    /// Read and/or write permissions on the admin space [default: r].
+    #[arg(long, value_name = "r|w|rw|none", value_parser = ["r", "w", "rw", "none"])]
+    pub adminspace_permissions: Option<String>,
+    /// Root CA certificate (PEM file) used to authenticate TLS peers.
+    #[arg(long, value_name = "FILE")]
+    pub tls_root_ca_certificate: Option<String>,
+    /// Private key (PEM file) of the TLS listeners.
+    #[arg(long, value_name = "FILE")]
+    pub tls_listen_private_key: Option<String>,
+    /// Certificate (PEM file) of the TLS listeners.
+    #[arg(long, value_name = "FILE")]
+    pub tls_listen_certificate: Option<String>,
+    /// Private key (PEM file) presented when connecting with mutual TLS.
+    #[arg(long, value_name = "FILE")]
+    pub tls_connect_private_key: Option<String>,
+    /// Certificate (PEM file) presented when connecting with mutual TLS.
+    #[arg(long, value_name = "FILE")]
+    pub tls_connect_certificate: Option<String>,
+    /// Require TLS peers to authenticate with a certificate (mutual TLS).
+    #[arg(long)]
+    pub tls_enable_mtls: bool,
+    /// User name to authenticate with (requires --auth-password or --auth-password-file).
+    #[arg(long, value_name = "USER", requires = "password")]
+    pub auth_user: Option<String>,
+    /// Password to authenticate with (requires --auth-user).
+    /// WARNING: visible to the other users of the machine, prefer --auth-password-file.
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        requires = "auth_user",
+        group = "password"
+    )]
+    pub auth_password: Option<String>,
+    /// File holding the password to authenticate with, instead of --auth-password (requires --auth-user).
+    #[arg(long, value_name = "FILE", requires = "auth_user", group = "password")]
+    pub auth_password_file: Option<String>,
+    /// File of `<user>:<password>` lines, the users allowed to connect.
+    #[arg(long, value_name = "FILE")]
+    pub auth_dictionary_file: Option<String>,
+    /// Allows arbitrary configuration changes as column-separated KEY:VALUE pairs, where:
+    ///   - KEY must be a valid config path.
+    ///   - VALUE must be a valid JSON5 string that can be deserialized to the expected type for the KEY field.
+    /// Examples: `--cfg='transport/unicast/max_links:2'`, `--cfg='plugins/mavlink/to_zenoh:true'`
+    /// Applied after every other argument, so they take precedence.
+    #[arg(long, value_name = "KEY:VALUE", verbatim_doc_comment)]
+    pub cfg: Vec<String>,
 }
 
-impl From<CommonArgs> for Config {
-    fn from(value: CommonArgs) -> Self {
-        (&value).into()
+impl TryFrom<CommonArgs> for Config {
+    type Error = String;
+
+    fn try_from(value: CommonArgs) -> Result<Self, Self::Error> {
+        (&value).try_into()
     }
 }
-impl From<&CommonArgs> for Config {
-    fn from(value: &CommonArgs) -> Self {
+
+/// Builds the configuration from the file and the arguments, except `--cfg` which is applied last (see
+/// [`insert_cfg`](crate::bridge_args::insert_cfg)).
+impl TryFrom<&CommonArgs> for Config {
+    type Error = String;
+
+    fn try_from(value: &CommonArgs) -> Result<Self, Self::Error> {
         let mut config = match &value.config {
-            Some(path) => Config::from_file(path).unwrap(),
+            Some(path) => Config::from_file(path).map_err(|e| format!("--config {path}: {e}"))?,
             None => Config::default(),
         };
         if let Some(id) = &value.id {
-            let _ = config.set_id(
-                id.parse()
-                    .expect("Error with option --id (expecting a hexadecimal ZenohId)"),
-            );
+            let id = id
+                .parse()
+                .map_err(|e| format!("--id {id} (expecting a hexadecimal ZenohId): {e}"))?;
+            let _ = config.set_id(id);
         }
         if value.mode.is_some() {
             // apply mode set via command line, overwritting mode set in config file
             config
                 .set_mode(value.mode.map(Into::into))
-                .expect("Error with option --mode");
+                .map_err(|_| "invalid --mode".to_string())?;
         } else if config.mode().is_none() {
             // no mode set neither via command line, neither in config file - set Router mode by default
             config
@@ -95,18 +150,20 @@ impl From<&CommonArgs> for Config {
                 .unwrap();
         }
         if !value.connect.is_empty() {
-            config
+            let endpoints = value
                 .connect
-                .endpoints
-                .set(value.connect.iter().map(|v| v.parse().unwrap()).collect())
-                .unwrap();
+                .iter()
+                .map(|v| v.parse().map_err(|e| format!("--connect {v}: {e}")))
+                .collect::<Result<_, _>>()?;
+            config.connect.endpoints.set(endpoints).unwrap();
         }
         if !value.listen.is_empty() {
-            config
+            let endpoints = value
                 .listen
-                .endpoints
-                .set(value.listen.iter().map(|v| v.parse().unwrap()).collect())
-                .unwrap();
+                .iter()
+                .map(|v| v.parse().map_err(|e| format!("--listen {v}: {e}")))
+                .collect::<Result<_, _>>()?;
+            config.listen.endpoints.set(endpoints).unwrap();
         }
         if value.no_multicast_scouting {
             config.scouting.multicast.set_enabled(Some(false)).unwrap();
@@ -115,11 +172,213 @@ impl From<&CommonArgs> for Config {
             #[cfg(feature = "shared-memory")]
             config.transport.shared_memory.set_enabled(true).unwrap();
             #[cfg(not(feature = "shared-memory"))]
-            {
-                println!("enable-shm argument: SHM cannot be enabled, because Zenoh is compiled without shared-memory feature!");
-                std::process::exit(-1);
+            return Err("--enable-shm: SHM cannot be enabled, because Zenoh is compiled without shared-memory feature".to_string());
+        }
+        if let Some(permissions) = &value.adminspace_permissions {
+            let (read, write) = match permissions.as_str() {
+                "r" => (true, false),
+                "w" => (false, true),
+                "rw" => (true, true),
+                _ => (false, false),
+            };
+            insert_json5(
+                &mut config,
+                "adminspace/permissions",
+                &serde_json::json!({ "read": read, "write": write }),
+            )?;
+        }
+        let tls = [
+            ("root_ca_certificate", &value.tls_root_ca_certificate),
+            ("listen_private_key", &value.tls_listen_private_key),
+            ("listen_certificate", &value.tls_listen_certificate),
+            ("connect_private_key", &value.tls_connect_private_key),
+            ("connect_certificate", &value.tls_connect_certificate),
+        ];
+        for (key, path) in tls {
+            insert_json5_option(&mut config, &format!("transport/link/tls/{key}"), path)?;
+        }
+        if value.tls_enable_mtls {
+            insert_json5(&mut config, "transport/link/tls/enable_mtls", &true)?;
+        }
+        let password = match &value.auth_password_file {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("--auth-password-file {path}: {e}"))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+            ),
+            None => value.auth_password.clone(),
+        };
+        let usrpwd = [
+            ("user", &value.auth_user),
+            ("password", &password),
+            ("dictionary_file", &value.auth_dictionary_file),
+        ];
+        if usrpwd.iter().any(|(_, v)| v.is_some()) {
+            // user and password are inserted together, as one is rejected without the other
+            let mut auth: serde_json::Value = config
+                .get_json("transport/auth/usrpwd")
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_else(|| serde_json::json!({}));
+            for (key, v) in usrpwd {
+                if let Some(v) = v {
+                    auth[key] = v.clone().into();
+                }
             }
+            insert_json5(&mut config, "transport/auth/usrpwd", &auth)?;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+    use zenoh::config::Config;
+
+    use super::CommonArgs;
+
+    fn config(args: &[&str]) -> Config {
+        let args = CommonArgs::parse_from(["zenoh-bridge-mavlink"].iter().chain(args));
+        Config::try_from(&args).unwrap()
+    }
+
+    fn get(config: &Config, key: &str) -> serde_json::Value {
+        serde_json::from_str(&config.get_json(key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn id() {
+        assert_eq!(get(&config(&["--id", "a0b23"]), "id"), "a0b23");
+        assert_eq!(get(&config(&["-i", "a0b23"]), "id"), "a0b23");
+    }
+
+    #[test]
+    fn mode() {
+        assert_eq!(get(&config(&[]), "mode"), "router");
+        assert_eq!(get(&config(&["--mode", "peer"]), "mode"), "peer");
+        assert_eq!(get(&config(&["-m", "client"]), "mode"), "client");
+    }
+
+    #[test]
+    fn endpoints() {
+        let config = config(&[
+            "-e",
+            "tcp/10.0.0.1:7447",
+            "--connect",
+            "tcp/10.0.0.2:7447",
+            "-l",
+            "tcp/0.0.0.0:7447",
+        ]);
+        assert_eq!(
+            get(&config, "connect/endpoints"),
+            json!(["tcp/10.0.0.1:7447", "tcp/10.0.0.2:7447"])
+        );
+        assert_eq!(
+            get(&config, "listen/endpoints"),
+            json!(["tcp/0.0.0.0:7447"])
+        );
+    }
+
+    #[test]
+    fn no_multicast_scouting() {
+        let config = config(&["--no-multicast-scouting"]);
+        assert_eq!(get(&config, "scouting/multicast/enabled"), false);
+    }
+
+    #[test]
+    fn adminspace_permissions() {
+        for (permissions, read, write) in [
+            ("r", true, false),
+            ("w", false, true),
+            ("rw", true, true),
+            ("none", false, false),
+        ] {
+            let config = config(&["--adminspace-permissions", permissions]);
+            assert_eq!(
+                get(&config, "adminspace/permissions"),
+                json!({ "read": read, "write": write })
+            );
+        }
+        assert!(CommonArgs::try_parse_from(["bridge", "--adminspace-permissions", "x"]).is_err());
+    }
+
+    #[test]
+    fn tls() {
+        let config = config(&[
+            "--tls-root-ca-certificate",
+            "ca.pem",
+            "--tls-listen-private-key",
+            "listen.key",
+            "--tls-listen-certificate",
+            "listen.pem",
+            "--tls-connect-private-key",
+            "connect.key",
+            "--tls-connect-certificate",
+            "connect.pem",
+            "--tls-enable-mtls",
+        ]);
+        for (key, value) in [
+            ("root_ca_certificate", json!("ca.pem")),
+            ("listen_private_key", json!("listen.key")),
+            ("listen_certificate", json!("listen.pem")),
+            ("connect_private_key", json!("connect.key")),
+            ("connect_certificate", json!("connect.pem")),
+            ("enable_mtls", json!(true)),
+        ] {
+            assert_eq!(get(&config, &format!("transport/link/tls/{key}")), value);
         }
-        config
+    }
+
+    #[test]
+    fn auth() {
+        let config = config(&[
+            "--auth-user",
+            "bridge",
+            "--auth-password",
+            "secret",
+            "--auth-dictionary-file",
+            "users.txt",
+        ]);
+        assert_eq!(get(&config, "transport/auth/usrpwd/user"), "bridge");
+        assert_eq!(get(&config, "transport/auth/usrpwd/password"), "secret");
+        assert_eq!(
+            get(&config, "transport/auth/usrpwd/dictionary_file"),
+            "users.txt"
+        );
+        assert!(CommonArgs::try_parse_from(["bridge", "--auth-user", "bridge"]).is_err());
+    }
+
+    #[test]
+    fn auth_password_file() {
+        let path =
+            std::env::temp_dir().join(format!("zenoh-bridge-mavlink-{}", std::process::id()));
+        std::fs::write(&path, "secret\n").unwrap();
+        let path = path.to_str().unwrap();
+        let config = config(&["--auth-user", "bridge", "--auth-password-file", path]);
+        assert_eq!(get(&config, "transport/auth/usrpwd/user"), "bridge");
+        assert_eq!(get(&config, "transport/auth/usrpwd/password"), "secret");
+        std::fs::remove_file(path).unwrap();
+
+        assert!(CommonArgs::try_parse_from([
+            "bridge",
+            "--auth-user",
+            "bridge",
+            "--auth-password",
+            "secret",
+            "--auth-password-file",
+            path,
+        ])
+        .is_err());
+        let args = CommonArgs::parse_from([
+            "bridge",
+            "--auth-user",
+            "bridge",
+            "--auth-password-file",
+            path,
+        ]);
+        assert!(Config::try_from(&args).is_err());
     }
 }
@@ -0,0 +1,241 @@
+//! Compares publishing one frame per Zenoh sample with batching, between two sessions over TCP loopback.
+//!
+//! Run with `cargo bench -p zenoh-plugin-mavlink --bench batching`. For each mode, a burst of frames gives
+//! the throughput, and frames paced at 1kHz give the latency (from `put` to reception).
+
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mavio::dialects::common::messages::Heartbeat;
+use zenoh::{pubsub::Publisher, Session};
+use zenoh_plugin_mavlink::{
+    batch::{split_batch, BatchConfig, Batcher},
+    component::Component,
+    encoding::batch_encoding,
+    protocol::{parse_raw_frame, Protocol},
+};
+
+const KEY: &str = "bench/mavlink";
+const BURST_FRAMES: u32 = 20_000;
+const PACED_FRAMES: u32 = 2_000;
+const PACED_INTERVAL: Duration = Duration::from_millis(1);
+
+async fn open_session(listen: Option<u16>, connect: Option<u16>) -> Session {
+    let mut config = zenoh::Config::default();
+    config.insert_json5("mode", r#""peer""#).unwrap();
+    config
+        .insert_json5("scouting/multicast/enabled", "false")
+        .unwrap();
+    let listen = listen.map(|port| format!(r#"["tcp/127.0.0.1:{port}"]"#));
+    config
+        .insert_json5("listen/endpoints", listen.as_deref().unwrap_or("[]"))
+        .unwrap();
+    if let Some(port) = connect {
+        config
+            .insert_json5("connect/endpoints", &format!(r#"["tcp/127.0.0.1:{port}"]"#))
+            .unwrap();
+    }
+    zenoh::open(config).await.unwrap()
+}
+
+/// Raw `HEARTBEAT` frame carrying its index in `custom_mode`.
+fn frame(component: &Component, index: u32) -> Vec<u8> {
+    let heartbeat = Heartbeat {
+        custom_mode: index,
+        ..Default::default()
+    };
+    Protocol::new("bench", component.frame("bench", &heartbeat).unwrap()).raw_frame()
+}
+
+struct Stats {
+    received: u32,
+    latencies: Vec<Duration>,
+}
+
+/// Publishes `count` frames every `interval` (or as fast as possible), returning the elapsed time and the stats of the receiver.
+async fn run(
+    publisher: &Publisher<'_>,
+    batch: Option<&BatchConfig>,
+    count: u32,
+    interval: Option<Duration>,
+    sent: &Arc<Mutex<Vec<Instant>>>,
+    stats: &Arc<Mutex<Stats>>,
+) -> Duration {
+    let component = Component::new(1, 1, 2);
+    *sent.lock().unwrap() = Vec::with_capacity(count as usize);
+    *stats.lock().unwrap() = Stats {
+        received: 0,
+        latencies: Vec::new(),
+    };
+    let mut batcher = batch.map(Batcher::new);
+
+    let start = Instant::now();
+    for index in 0..count {
+        if let Some(interval) = interval {
+            tokio::time::sleep_until((start + interval * index).into()).await;
+        }
+        let frame = frame(&component, index);
+        sent.lock().unwrap().push(Instant::now());
+        match batcher.as_mut() {
+            Some(batcher) => {
+                for batch in batcher.push(0, &frame) {
+                    publisher
+                        .put(batch)
+                        .encoding(batch_encoding())
+                        .await
+                        .unwrap();
+                }
+                for (_, batch) in batcher.expired() {
+                    publisher
+                        .put(batch)
+                        .encoding(batch_encoding())
+                        .await
+                        .unwrap();
+                }
+            }
+            None => publisher.put(frame).await.unwrap(),
+        }
+    }
+    if let Some(batcher) = batcher.as_mut() {
+        for (_, batch) in batcher.flush() {
+            publisher
+                .put(batch)
+                .encoding(batch_encoding())
+                .await
+                .unwrap();
+        }
+    }
+
+    // wait for every frame, or for the link to stay silent
+    let mut last = stats.lock().unwrap().received;
+    while last < count {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let received = stats.lock().unwrap().received;
+        if received == last {
+            break;
+        }
+        last = received;
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, count: u32, elapsed: Duration, stats: &Stats) {
+    let mut latencies = stats.latencies.clone();
+    latencies.sort();
+    let percentile = |p: f64| {
+        latencies
+            .get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!(
+        "{name:<32} {:>6}/{count} frames {:>10.0} frames/s   latency p50 {:>9.3?} p99 {:>9.3?}",
+        stats.received,
+        stats.received as f64 / elapsed.as_secs_f64(),
+        percentile(0.5),
+        percentile(0.99),
+    );
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let publisher_session = open_session(Some(port), None).await;
+    let subscriber_session = open_session(None, Some(port)).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let stats = Arc::new(Mutex::new(Stats {
+        received: 0,
+        latencies: Vec::new(),
+    }));
+    let _subscriber = {
+        let sent = sent.clone();
+        let stats = stats.clone();
+        subscriber_session
+            .declare_subscriber(KEY)
+            .callback(move |sample| {
+                let now = Instant::now();
+                let bytes = sample.payload().to_bytes();
+                let frames = if sample.encoding() == &batch_encoding() {
+                    split_batch(&bytes).unwrap()
+                } else {
+                    vec![&bytes[..]]
+                };
+                let mut stats = stats.lock().unwrap();
+                let sent = sent.lock().unwrap();
+                for frame in frames {
+                    let heartbeat = Protocol::new("bench", parse_raw_frame(frame).unwrap())
+                        .decode::<Heartbeat>()
+                        .unwrap();
+                    stats.received += 1;
+                    if let Some(sent) = sent.get(heartbeat.custom_mode as usize) {
+                        stats.latencies.push(now.duration_since(*sent));
+                    }
+                }
+            })
+            .await
+            .unwrap()
+    };
+    let publisher = publisher_session.declare_publisher(KEY).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let modes = [
+        ("unbatched", None),
+        (
+            "batched (1ms, 1024 bytes)",
+            Some(BatchConfig {
+                window: 0.001,
+                max_bytes: 1024,
+            }),
+        ),
+        ("batched (10ms, 1024 bytes)", Some(BatchConfig::default())),
+        (
+            "batched (10ms, 8192 bytes)",
+            Some(BatchConfig {
+                window: 0.01,
+                max_bytes: 8192,
+            }),
+        ),
+    ];
+    for (name, batch) in &modes {
+        let elapsed = run(
+            &publisher,
+            batch.as_ref(),
+            BURST_FRAMES,
+            None,
+            &sent,
+            &stats,
+        )
+        .await;
+        report(
+            &format!("{name} burst"),
+            BURST_FRAMES,
+            elapsed,
+            &stats.lock().unwrap(),
+        );
+
+        let elapsed = run(
+            &publisher,
+            batch.as_ref(),
+            PACED_FRAMES,
+            Some(PACED_INTERVAL),
+            &sent,
+            &stats,
+        )
+        .await;
+        report(
+            &format!("{name} 1kHz"),
+            PACED_FRAMES,
+            elapsed,
+            &stats.lock().unwrap(),
+        );
+    }
+}
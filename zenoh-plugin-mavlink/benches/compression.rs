@@ -0,0 +1,154 @@
+//! Measures the bytes published on Zenoh for a recorded `.tlog`, with and without batching and compression.
+//!
+//! Run with `TLOG=path/to/flight.tlog cargo bench -p zenoh-plugin-mavlink --bench compression`.
+//! The baseline is one sample per frame, as published by `From<Protocol> for ZBytes`. Batches are rebuilt
+//! from the timestamps of the tlog, as the bridge would have published them live.
+
+use std::time::{Duration, Instant};
+
+use zenoh::bytes::ZBytes;
+use zenoh_plugin_mavlink::{
+    batch::{encode_frame, BatchConfig},
+    compression::{decompress, CompressionAlgorithm, CompressionConfig},
+    protocol::Protocol,
+    replay::TlogReader,
+};
+
+/// Reads the frames of the tlog, serialized as published, with their timestamp.
+async fn read_frames(tlog: &[u8]) -> Vec<(u64, Vec<u8>)> {
+    let mut records = TlogReader::new(tlog);
+    let mut frames = Vec::new();
+    while let Some((timestamp, mav_frame)) =
+        records.next_record().await.expect("unable to read TLOG")
+    {
+        let bytes = ZBytes::from(Protocol::new("tlog", mav_frame));
+        frames.push((timestamp, bytes.to_bytes().to_vec()));
+    }
+    frames
+}
+
+/// Payloads published for each frame of the tlog.
+fn unbatched(frames: &[(u64, Vec<u8>)]) -> Vec<Vec<u8>> {
+    frames.iter().map(|(_, frame)| frame.clone()).collect()
+}
+
+/// Payloads published when batching the frames of the tlog.
+fn batched(frames: &[(u64, Vec<u8>)], config: &BatchConfig) -> Vec<Vec<u8>> {
+    let window = Duration::from_secs_f32(config.window).as_micros() as u64;
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_start = 0;
+    for (timestamp, frame) in frames {
+        if !batch.is_empty()
+            && (timestamp.saturating_sub(batch_start) > window
+                || batch.len() + 2 + frame.len() > config.max_bytes)
+        {
+            batches.push(std::mem::take(&mut batch));
+        }
+        if batch.is_empty() {
+            batch_start = *timestamp;
+        }
+        encode_frame(&mut batch, frame);
+        if batch.len() >= config.max_bytes {
+            batches.push(std::mem::take(&mut batch));
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+fn report(
+    name: &str,
+    payloads: &[Vec<u8>],
+    compression: Option<&CompressionConfig>,
+    baseline: usize,
+) {
+    let start = Instant::now();
+    let mut total = 0;
+    let mut compressed_count = 0;
+    for payload in payloads {
+        match compression.and_then(|c| c.compress(payload).map(|p| (c.algorithm, p))) {
+            Some((algorithm, compressed)) => {
+                assert_eq!(&decompress(algorithm, &compressed).unwrap(), payload);
+                total += compressed.len();
+                compressed_count += 1;
+            }
+            None => total += payload.len(),
+        }
+    }
+    println!(
+        "{name:<28} {:>8} samples ({compressed_count:>8} compressed) {total:>12} bytes {:>6.1}% of baseline   {:>10.3?}",
+        payloads.len(),
+        100.0 * total as f64 / baseline as f64,
+        start.elapsed(),
+    );
+}
+
+fn main() {
+    let Ok(path) = std::env::var("TLOG") else {
+        println!("set TLOG to the path of a .tlog file to measure compression on");
+        return;
+    };
+    let tlog = std::fs::read(&path).expect("unable to read TLOG");
+    let frames = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(read_frames(&tlog));
+    let baseline: usize = frames.iter().map(|(_, frame)| frame.len()).sum();
+    println!("{path}: {} frames, {baseline} bytes", frames.len());
+
+    let compressions = [
+        ("", None),
+        (
+            " + zstd",
+            Some(CompressionConfig {
+                algorithm: CompressionAlgorithm::Zstd,
+                level: 3,
+                min_size: 0,
+            }),
+        ),
+        (
+            " + zstd (level 19)",
+            Some(CompressionConfig {
+                algorithm: CompressionAlgorithm::Zstd,
+                level: 19,
+                min_size: 0,
+            }),
+        ),
+        (
+            " + lz4",
+            Some(CompressionConfig {
+                algorithm: CompressionAlgorithm::Lz4,
+                level: 0,
+                min_size: 0,
+            }),
+        ),
+    ];
+    let batchings = [
+        ("unbatched", None),
+        ("batched 10ms/1024", Some(BatchConfig::default())),
+        (
+            "batched 100ms/8192",
+            Some(BatchConfig {
+                window: 0.1,
+                max_bytes: 8192,
+            }),
+        ),
+    ];
+    for (batching, batch) in &batchings {
+        let payloads = match batch {
+            Some(config) => batched(&frames, config),
+            None => unbatched(&frames),
+        };
+        for (suffix, compression) in &compressions {
+            report(
+                &format!("{batching}{suffix}"),
+                &payloads,
+                compression.as_ref(),
+                baseline,
+            );
+        }
+    }
+}
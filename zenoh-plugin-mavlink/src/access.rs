@@ -0,0 +1,369 @@
+//! Authorization of the frames injected from zenoh (with `from_zenoh`) and of the commands, parameter sets and
+//! mission uploads/clears requested via zenoh queries.
+//!
+//! Each frame is checked against the rule of the system it targets (`systems`, or `default` for the systems
+//! without rule). A frame without target system, or broadcast to every system (target 0), must be allowed by
+//! every rule. A rule may restrict the zenoh ids of the sources (`sources`) and the key expressions the frames
+//! are put on (`keys`, e.g. `@/*/@mavlink/v2/in/gcs/**`). The commands of `denied_commands` (`COMMAND_LONG`,
+//! `COMMAND_INT` and mission items) and the messages of `denied_messages` are rejected unless the rule permits them
+//! (`commands`, `messages`).
+//!
+//! Rejected frames are logged as warnings. With `audit`, every accepted command (and permitted message of
+//! `denied_messages`) is logged with its key and source, under the `zenoh_plugin_mavlink::access` target.
+//!
+//! The source of a frame is the zenoh id of the publisher's [source info](zenoh::sample::SourceInfo), only set
+//! by publishers attaching it, and not authenticated: zenoh's access control and authentication should restrict
+//! who can put on `@mavlink/v2/in` in the first place.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use mavio::dialects::common::messages::{
+    CommandInt, CommandLong, ManualControl, MissionClearAll, MissionCount, MissionItem,
+    MissionItemInt, MissionRequestList, ParamRequestList, ParamRequestRead, ParamSet,
+    RcChannelsOverride, SetAttitudeTarget, SetMode, SetPositionTargetGlobalInt,
+    SetPositionTargetLocalNed,
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+use zenoh::key_expr::{keyexpr, OwnedKeyExpr};
+
+use crate::protocol::Protocol;
+
+/// `MAV_CMD_DO_FLIGHTTERMINATION`, `MAV_CMD_PREFLIGHT_REBOOT_SHUTDOWN`, `MAV_CMD_COMPONENT_ARM_DISARM`
+pub const DEFAULT_DENIED_COMMANDS: [u16; 3] = [185, 246, 400];
+pub const DEFAULT_DENIED_MESSAGES: [u32; 1] = [PARAM_SET];
+pub const PARAM_SET: u32 = 23;
+pub const MISSION_COUNT: u32 = 44;
+pub const MISSION_CLEAR_ALL: u32 = 45;
+const MISSION_ITEM: u32 = 39;
+const MISSION_ITEM_INT: u32 = 73;
+const COMMAND_INT: u32 = 75;
+const COMMAND_LONG: u32 = 76;
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AccessConfig {
+    /// Target system id => rule of the frames sent to it.
+    #[serde(default)]
+    pub systems: HashMap<u8, AccessRule>,
+    /// Rule of the systems not in `systems`.
+    #[serde(default)]
+    pub default: AccessRule,
+    /// `MAV_CMD` ids rejected unless permitted by a rule.
+    #[serde(default = "default_denied_commands")]
+    pub denied_commands: HashSet<u16>,
+    /// Message ids rejected unless permitted by a rule.
+    #[serde(default = "default_denied_messages")]
+    pub denied_messages: HashSet<u32>,
+    /// Log every accepted command.
+    #[serde(default = "default_audit")]
+    pub audit: bool,
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        Self {
+            systems: HashMap::new(),
+            default: AccessRule::default(),
+            denied_commands: default_denied_commands(),
+            denied_messages: default_denied_messages(),
+            audit: default_audit(),
+        }
+    }
+}
+
+impl AccessConfig {
+    /// Returns the problems of the rules.
+    pub fn check(&self) -> Vec<String> {
+        let rules = std::iter::once(("default".to_string(), &self.default)).chain(
+            self.systems
+                .iter()
+                .map(|(system_id, rule)| (format!("systems/{system_id}"), rule)),
+        );
+        let mut problems = Vec::new();
+        for (name, rule) in rules {
+            for key in &rule.keys {
+                if let Err(e) = OwnedKeyExpr::try_from(key.clone()) {
+                    problems.push(format!("{name}: invalid key expression {key}: {e}"));
+                }
+            }
+        }
+        problems
+    }
+}
+
+fn default_denied_commands() -> HashSet<u16> {
+    DEFAULT_DENIED_COMMANDS.into()
+}
+
+fn default_denied_messages() -> HashSet<u32> {
+    DEFAULT_DENIED_MESSAGES.into()
+}
+
+fn default_audit() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    /// Zenoh ids of the sources allowed, any if empty.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Key expressions the frames may be put on, any if empty.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Commands of `denied_commands` permitted.
+    #[serde(default)]
+    pub commands: HashSet<u16>,
+    /// Messages of `denied_messages` permitted.
+    #[serde(default)]
+    pub messages: HashSet<u32>,
+}
+
+impl AccessRule {
+    fn check(
+        &self,
+        keys: &[OwnedKeyExpr],
+        key: &keyexpr,
+        source: Option<&str>,
+    ) -> Result<(), String> {
+        if !self.sources.is_empty() && !source.is_some_and(|s| self.sources.iter().any(|a| a == s))
+        {
+            return Err("source not allowed".to_string());
+        }
+        if !keys.is_empty() && !keys.iter().any(|k| k.includes(key)) {
+            return Err("key not allowed".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What a frame injected from zenoh does.
+struct Injection {
+    message_id: u32,
+    /// Target system, `None` if the message has none.
+    target_system: Option<u8>,
+    /// `MAV_CMD` id of the commands and mission items.
+    command: Option<u16>,
+}
+
+impl Injection {
+    fn of(msg: &Protocol) -> Result<Self, String> {
+        let message_id = msg.mav_frame.message_id();
+        let (target_system, command) = if let Some(m) = msg.decode::<CommandLong>() {
+            (Some(m.target_system), Some(m.command as u16))
+        } else if let Some(m) = msg.decode::<CommandInt>() {
+            (Some(m.target_system), Some(m.command as u16))
+        } else if let Some(m) = msg.decode::<MissionItemInt>() {
+            (Some(m.target_system), Some(m.command as u16))
+        } else if let Some(m) = msg.decode::<MissionItem>() {
+            (Some(m.target_system), Some(m.command as u16))
+        } else if [COMMAND_LONG, COMMAND_INT, MISSION_ITEM_INT, MISSION_ITEM].contains(&message_id)
+        {
+            // an unknown command must not slip through the denied commands
+            return Err("undecodable command".to_string());
+        } else {
+            (target_system(msg), None)
+        };
+        Ok(Self {
+            message_id,
+            target_system,
+            command,
+        })
+    }
+}
+
+/// Target system of the messages (of the common dialect) addressed to a vehicle.
+fn target_system(msg: &Protocol) -> Option<u8> {
+    msg.decode::<ParamSet>()
+        .map(|m| m.target_system)
+        .or_else(|| msg.decode::<ParamRequestRead>().map(|m| m.target_system))
+        .or_else(|| msg.decode::<ParamRequestList>().map(|m| m.target_system))
+        .or_else(|| msg.decode::<SetMode>().map(|m| m.target_system))
+        .or_else(|| msg.decode::<MissionCount>().map(|m| m.target_system))
+        .or_else(|| msg.decode::<MissionClearAll>().map(|m| m.target_system))
+        .or_else(|| msg.decode::<MissionRequestList>().map(|m| m.target_system))
+        .or_else(|| {
+            msg.decode::<SetPositionTargetLocalNed>()
+                .map(|m| m.target_system)
+        })
+        .or_else(|| {
+            msg.decode::<SetPositionTargetGlobalInt>()
+                .map(|m| m.target_system)
+        })
+        .or_else(|| msg.decode::<SetAttitudeTarget>().map(|m| m.target_system))
+        .or_else(|| msg.decode::<ManualControl>().map(|m| m.target))
+        .or_else(|| msg.decode::<RcChannelsOverride>().map(|m| m.target_system))
+}
+
+/// Authorizes the frames and commands coming from zenoh.
+pub struct Access {
+    config: AccessConfig,
+    /// Parsed `keys` of the rules.
+    default_keys: Vec<OwnedKeyExpr>,
+    system_keys: HashMap<u8, Vec<OwnedKeyExpr>>,
+}
+
+impl Access {
+    /// Invalid key expressions are ignored: the plugin does not start with them (see [`AccessConfig::check`]).
+    pub fn new(config: &AccessConfig) -> Self {
+        let parse = |rule: &AccessRule| {
+            rule.keys
+                .iter()
+                .filter_map(|key| OwnedKeyExpr::try_from(key.clone()).ok())
+                .collect::<Vec<_>>()
+        };
+        Self {
+            default_keys: parse(&config.default),
+            system_keys: config
+                .systems
+                .iter()
+                .map(|(system_id, rule)| (*system_id, parse(rule)))
+                .collect(),
+            config: config.clone(),
+        }
+    }
+
+    /// Whether `msg`, put on `key` by `source`, may be sent to the vehicles. Rejected frames are logged, as the
+    /// accepted commands with `audit`.
+    pub fn authorize(&self, msg: &Protocol, key: &keyexpr, source: Option<&str>) -> bool {
+        match Injection::of(msg) {
+            Ok(injection) => self.authorize_injection(&injection, key, source).is_ok(),
+            Err(e) => {
+                warn!(
+                    "rejected message {} on {key} from {}: {e}",
+                    msg.mav_frame.message_id(),
+                    source.unwrap_or("unknown source")
+                );
+                false
+            }
+        }
+    }
+
+    /// Authorizes `command` (sent as `COMMAND_LONG` to `system_id`), requested on `key` by `source`.
+    pub fn authorize_command(
+        &self,
+        system_id: u8,
+        command: u16,
+        key: &keyexpr,
+        source: Option<&str>,
+    ) -> Result<(), String> {
+        let injection = Injection {
+            message_id: COMMAND_LONG,
+            target_system: Some(system_id),
+            command: Some(command),
+        };
+        self.authorize_injection(&injection, key, source)
+    }
+
+    /// Authorizes message `message_id` to `system_id`, requested on `key` by `source`.
+    pub fn authorize_message(
+        &self,
+        system_id: u8,
+        message_id: u32,
+        key: &keyexpr,
+        source: Option<&str>,
+    ) -> Result<(), String> {
+        let injection = Injection {
+            message_id,
+            target_system: Some(system_id),
+            command: None,
+        };
+        self.authorize_injection(&injection, key, source)
+    }
+
+    /// Authorizes the upload of a mission running `commands` to `system_id`, requested on `key` by `source`.
+    pub fn authorize_mission(
+        &self,
+        system_id: u8,
+        commands: &BTreeSet<u16>,
+        key: &keyexpr,
+        source: Option<&str>,
+    ) -> Result<(), String> {
+        for command in commands {
+            let injection = Injection {
+                message_id: MISSION_ITEM_INT,
+                target_system: Some(system_id),
+                command: Some(*command),
+            };
+            self.authorize_injection(&injection, key, source)?;
+        }
+        self.authorize_message(system_id, MISSION_COUNT, key, source)
+    }
+
+    fn authorize_injection(
+        &self,
+        injection: &Injection,
+        key: &keyexpr,
+        source: Option<&str>,
+    ) -> Result<(), String> {
+        let what = match injection.command {
+            Some(command) => format!("command {command}"),
+            None => format!("message {}", injection.message_id),
+        };
+        let target = match injection.target_system {
+            Some(system_id) => format!("system {system_id}"),
+            None => "every system".to_string(),
+        };
+        match self.check(injection, key, source) {
+            Ok(()) => {
+                let audited = injection.command.is_some()
+                    || self.config.denied_messages.contains(&injection.message_id);
+                if self.config.audit && audited {
+                    info!(
+                        "accepted {what} to {target} on {key} from {}",
+                        source.unwrap_or("unknown source")
+                    );
+                }
+                Ok(())
+            }
+            Err(e) => {
+                warn!(
+                    "rejected {what} to {target} on {key} from {}: {e}",
+                    source.unwrap_or("unknown source")
+                );
+                Err(format!("{what} not authorized: {e}"))
+            }
+        }
+    }
+
+    fn check(
+        &self,
+        injection: &Injection,
+        key: &keyexpr,
+        source: Option<&str>,
+    ) -> Result<(), String> {
+        // a frame to every system must be allowed by every rule
+        let rules: Vec<(&AccessRule, &[OwnedKeyExpr])> = match injection.target_system {
+            Some(system_id) if system_id != 0 => match self.config.systems.get(&system_id) {
+                Some(rule) => vec![(rule, self.system_keys[&system_id].as_slice())],
+                None => vec![(&self.config.default, self.default_keys.as_slice())],
+            },
+            _ => std::iter::once((&self.config.default, self.default_keys.as_slice()))
+                .chain(
+                    self.config
+                        .systems
+                        .iter()
+                        .map(|(system_id, rule)| (rule, self.system_keys[system_id].as_slice())),
+                )
+                .collect(),
+        };
+        for (rule, keys) in rules {
+            rule.check(keys, key, source)?;
+            if let Some(command) = injection.command {
+                if self.config.denied_commands.contains(&command)
+                    && !rule.commands.contains(&command)
+                {
+                    return Err("command not permitted".to_string());
+                }
+            }
+            if self.config.denied_messages.contains(&injection.message_id)
+                && !rule.messages.contains(&injection.message_id)
+            {
+                return Err("message not permitted".to_string());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Packs several MAVLink frames in one Zenoh sample, for high-rate or constrained links.
+//!
+//! A batch is a sequence of frames, each prefixed by its length (little-endian `u16`), published with the
+//! [`batch_encoding`](crate::encoding::batch_encoding). Frames are held until the oldest one is `window`
+//! seconds old, or until the batch reaches `max_bytes`, and the pending batches are published when the plugin
+//! stops. Batches received on the `in` key expression are split back into frames.
+
+use std::{collections::HashMap, time::Duration};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+pub const DEFAULT_BATCH_WINDOW: f32 = 0.01;
+pub const DEFAULT_BATCH_MAX_BYTES: usize = 1024;
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BatchConfig {
+    /// Maximum time (in seconds) a frame is held before its batch is published.
+    #[serde(default = "default_window")]
+    pub window: f32,
+    /// A batch is published as soon as it reaches this size (in bytes).
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: default_window(),
+            max_bytes: default_max_bytes(),
+        }
+    }
+}
+
+fn default_window() -> f32 {
+    DEFAULT_BATCH_WINDOW
+}
+
+fn default_max_bytes() -> usize {
+    DEFAULT_BATCH_MAX_BYTES
+}
+
+/// Appends `frame` to `batch`, prefixed by its length.
+pub fn encode_frame(batch: &mut Vec<u8>, frame: &[u8]) {
+    batch.extend_from_slice(&(frame.len() as u16).to_le_bytes());
+    batch.extend_from_slice(frame);
+}
+
+/// Splits a batch into frames, or returns `None` if it is malformed.
+pub fn split_batch(mut batch: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut frames = Vec::new();
+    while !batch.is_empty() {
+        let len = u16::from_le_bytes(batch.get(..2)?.try_into().unwrap()) as usize;
+        frames.push(batch.get(2..2 + len)?);
+        batch = &batch[2 + len..];
+    }
+    Some(frames)
+}
+
+struct Batch {
+    bytes: Vec<u8>,
+    deadline: Instant,
+}
+
+/// Batches being filled, by key (e.g. the publisher they will be published with).
+pub struct Batcher {
+    window: Duration,
+    max_bytes: usize,
+    batches: HashMap<usize, Batch>,
+}
+
+impl Batcher {
+    pub fn new(config: &BatchConfig) -> Self {
+        Self {
+            window: Duration::from_secs_f32(config.window),
+            max_bytes: config.max_bytes,
+            batches: HashMap::new(),
+        }
+    }
+
+    /// Adds `frame` to the batch of `key`, returning the batches that are full.
+    pub fn push(&mut self, key: usize, frame: &[u8]) -> Vec<Vec<u8>> {
+        let mut full = Vec::new();
+        if self
+            .batches
+            .get(&key)
+            .is_some_and(|batch| batch.bytes.len() + 2 + frame.len() > self.max_bytes)
+        {
+            full.push(self.batches.remove(&key).unwrap().bytes);
+        }
+
+        let batch = self.batches.entry(key).or_insert_with(|| Batch {
+            bytes: Vec::with_capacity(self.max_bytes),
+            deadline: Instant::now() + self.window,
+        });
+        encode_frame(&mut batch.bytes, frame);
+        if batch.bytes.len() >= self.max_bytes {
+            full.push(self.batches.remove(&key).unwrap().bytes);
+        }
+        full
+    }
+
+    /// When the oldest batch must be published.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.batches.values().map(|batch| batch.deadline).min()
+    }
+
+    /// Removes the batches whose window is over.
+    pub fn expired(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let now = Instant::now();
+        let keys: Vec<usize> = self
+            .batches
+            .iter()
+            .filter(|(_, batch)| batch.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        keys.into_iter()
+            .map(|key| (key, self.batches.remove(&key).unwrap().bytes))
+            .collect()
+    }
+
+    /// Removes every batch.
+    pub fn flush(&mut self) -> Vec<(usize, Vec<u8>)> {
+        self.batches
+            .drain()
+            .map(|(key, batch)| (key, batch.bytes))
+            .collect()
+    }
+}
@@ -0,0 +1,216 @@
+//! MAVLink command protocol exposed as a Zenoh queryable.
+//!
+//! A query on `@/<zenoh_id>/@mavlink/v2/cmd/<system_id>/<component_id>` carries a JSON [`CommandRequest`].
+//! The command is sent as `COMMAND_LONG` through the connection the target was last heard on and
+//! retransmitted until a matching `COMMAND_ACK` arrives. Every ack (including `IN_PROGRESS` updates)
+//! is replied as a JSON [`CommandReply`]; the query gets an error reply if the vehicle never answers.
+//!
+//! Commands are authorized as the ones injected from zenoh (see [`crate::access`]), without a known source.
+//!
+//! Optional selector parameters: `timeout` (ack timeout in milliseconds) and `retries`.
+
+use std::{sync::Arc, time::Duration};
+
+use mavio::dialects::common::{
+    enums::{MavCmd, MavResult},
+    messages::{CommandAck, CommandLong},
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+use zenoh::{key_expr::format::keformat, query::Query, Session};
+
+use crate::{
+    access::Access,
+    liveliness::ke_liveliness_cmd,
+    routing::Router,
+    service::{parameter, parse_target, recv_from, timeout_parameter},
+};
+
+/// Time to wait for a `COMMAND_ACK` before retransmitting.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Number of retransmissions before giving up.
+pub const DEFAULT_RETRIES: u8 = 3;
+/// Time to wait for the next update once the vehicle reported `IN_PROGRESS`.
+pub const PROGRESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CommandRequest {
+    /// `MAV_CMD` id.
+    pub command: u16,
+    /// Up to 7 command parameters, missing ones are set to 0.
+    #[serde(default)]
+    pub params: Vec<f32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CommandReply {
+    pub command: u16,
+    /// `MAV_RESULT` value.
+    pub result: u8,
+    pub progress: u8,
+    pub result_param2: i32,
+}
+
+#[derive(Clone)]
+pub(crate) struct CommandService {
+    zsession: Arc<Session>,
+    router: Router,
+    access: Arc<Access>,
+}
+
+impl CommandService {
+    pub fn new(zsession: Arc<Session>, router: Router, access: Arc<Access>) -> Self {
+        Self {
+            zsession,
+            router,
+            access,
+        }
+    }
+
+    pub async fn run(self) {
+        let ke = keformat!(
+            ke_liveliness_cmd::formatter(),
+            zenoh_id = self.zsession.zid().into_keyexpr(),
+            system_id = "*",
+            component_id = "*",
+        )
+        .unwrap();
+        let queryable = match self.zsession.declare_queryable(ke.clone()).await {
+            Ok(queryable) => queryable,
+            Err(e) => {
+                error!("unable to declare command queryable on {ke}: {e}");
+                return;
+            }
+        };
+
+        info!("serving MAVLink commands on {ke}");
+        while let Ok(query) = queryable.recv_async().await {
+            let service = self.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = service.handle(&query).await {
+                        warn!("command query {} failed: {e}", query.selector());
+                        if let Err(e) = query.reply_err(e).await {
+                            error!("failed to reply to command query: {e}");
+                        }
+                    }
+                }
+                .instrument(debug_span!("mav_cmd")),
+            );
+        }
+    }
+
+    async fn handle(&self, query: &Query) -> Result<(), String> {
+        let ke = ke_liveliness_cmd::parse(query.key_expr()).map_err(|e| e.to_string())?;
+        let (system_id, component_id) = parse_target(ke.system_id(), ke.component_id())?;
+
+        let request: CommandRequest = match query.payload() {
+            Some(payload) => {
+                serde_json::from_slice(&payload.to_bytes()).map_err(|e| e.to_string())?
+            }
+            None => return Err("missing command in query payload".to_string()),
+        };
+        if request.params.len() > 7 {
+            return Err(format!(
+                "too many parameters ({} > 7)",
+                request.params.len()
+            ));
+        }
+        self.access
+            .authorize_command(system_id, request.command, query.key_expr(), None)?;
+        let command = MavCmd::try_from(request.command)
+            .map_err(|_| format!("unknown command {}", request.command))?;
+
+        let ack_timeout = timeout_parameter(query, "timeout", DEFAULT_ACK_TIMEOUT)?;
+        let retries = parameter(query, "retries", DEFAULT_RETRIES)?;
+
+        let mut params = [0f32; 7];
+        params[..request.params.len()].copy_from_slice(&request.params);
+        let mut message = CommandLong {
+            target_system: system_id,
+            target_component: component_id,
+            command,
+            confirmation: 0,
+            param1: params[0],
+            param2: params[1],
+            param3: params[2],
+            param4: params[3],
+            param5: params[4],
+            param6: params[5],
+            param7: params[6],
+        };
+
+        // subscribe before sending so the ack cannot be missed
+        let mut rx = self.router.subscribe();
+        let mut in_progress = false;
+        for confirmation in 0..=retries {
+            // once the vehicle reported progress, the command must not be sent again
+            if !in_progress {
+                message.confirmation = confirmation;
+                debug!(
+                    "sending command {} to {system_id}/{component_id} (confirmation = {confirmation})",
+                    request.command
+                );
+                self.router.send(system_id, component_id, &message)?;
+            }
+
+            let mut deadline = Instant::now() + ack_timeout;
+            while let Some(ack) = recv_from(
+                &mut rx,
+                system_id,
+                component_id,
+                deadline,
+                |ack: &CommandAck| ack.command == command && self.is_ack_for_us(ack),
+            )
+            .await?
+            {
+                let reply = CommandReply {
+                    command: request.command,
+                    result: ack.result as u8,
+                    progress: ack.progress,
+                    result_param2: ack.result_param2,
+                };
+                debug!("command {} acknowledged: {reply:?}", request.command);
+                query
+                    .reply(
+                        query.key_expr().clone(),
+                        serde_json::to_vec(&reply).unwrap(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                if ack.result != MavResult::InProgress {
+                    return Ok(());
+                }
+                in_progress = true;
+                deadline = Instant::now() + PROGRESS_TIMEOUT;
+            }
+
+            if in_progress {
+                break;
+            }
+        }
+
+        if in_progress {
+            Err(format!(
+                "timeout waiting for command {} to complete",
+                request.command
+            ))
+        } else {
+            Err(format!(
+                "no COMMAND_ACK from {system_id}/{component_id} for command {} after {} attempts",
+                request.command,
+                retries as u16 + 1
+            ))
+        }
+    }
+
+    /// Acks are addressed to the command sender. Target 0 (broadcast) is accepted for older autopilots.
+    fn is_ack_for_us(&self, ack: &CommandAck) -> bool {
+        let component = &self.router.component;
+        (ack.target_system == 0 || ack.target_system == component.system_id)
+            && (ack.target_component == 0 || ack.target_component == component.component_id)
+    }
+}
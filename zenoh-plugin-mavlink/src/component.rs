@@ -0,0 +1,150 @@
+//! MAVLink identity of the plugin, used for every frame it originates.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use mavio::{
+    dialects::common::{
+        enums::{MavAutopilot, MavState, MavType},
+        messages::Heartbeat,
+    },
+    errors::Result,
+    protocol::{MavLinkVersion, V1, V2},
+    Frame, MavFrame, Message,
+};
+use tracing::{error, trace};
+
+use crate::{config::Config, routing::Router};
+
+/// MAVLink protocol version advertised in heartbeats.
+const MAVLINK_VERSION: u8 = 3;
+
+#[derive(Debug)]
+pub struct Component {
+    pub system_id: u8,
+    pub component_id: u8,
+    /// `MAV_TYPE` advertised in heartbeats.
+    pub mav_type: u8,
+    /// Sequence numbers are counted per connection, so each link sees a gapless sequence from the plugin.
+    sequences: Mutex<HashMap<String, u8>>,
+}
+
+impl From<&Config> for Component {
+    fn from(config: &Config) -> Self {
+        Self::new(config.system_id, config.component_id, config.mav_type)
+    }
+}
+
+impl Component {
+    pub fn new(system_id: u8, component_id: u8, mav_type: u8) -> Self {
+        Self {
+            system_id,
+            component_id,
+            mav_type,
+            sequences: Default::default(),
+        }
+    }
+
+    /// Builds a MAVLink 2 frame for `message` to be written to `endpoint`, consuming the next sequence number of this connection.
+    pub fn frame<M: Message>(&self, endpoint: &str, message: &M) -> Result<MavFrame> {
+        self.frame_as(endpoint, self.system_id, self.component_id, message)
+    }
+
+    /// Same as [`Component::frame`], of the given MAVLink `version`.
+    pub fn versioned_frame<M: Message>(
+        &self,
+        endpoint: &str,
+        version: MavLinkVersion,
+        message: &M,
+    ) -> Result<MavFrame> {
+        self.build(
+            endpoint,
+            self.system_id,
+            self.component_id,
+            version,
+            message,
+        )
+    }
+
+    /// Same as [`Component::frame`], on behalf of another system/component.
+    pub fn frame_as<M: Message>(
+        &self,
+        endpoint: &str,
+        system_id: u8,
+        component_id: u8,
+        message: &M,
+    ) -> Result<MavFrame> {
+        self.build(
+            endpoint,
+            system_id,
+            component_id,
+            MavLinkVersion::V2,
+            message,
+        )
+    }
+
+    fn build<M: Message>(
+        &self,
+        endpoint: &str,
+        system_id: u8,
+        component_id: u8,
+        version: MavLinkVersion,
+        message: &M,
+    ) -> Result<MavFrame> {
+        let sequence = {
+            let mut sequences = self.sequences.lock().unwrap();
+            let sequence = sequences.entry(endpoint.to_string()).or_default();
+            let current = *sequence;
+            *sequence = sequence.wrapping_add(1);
+            current
+        };
+
+        let builder = Frame::builder()
+            .sequence(sequence)
+            .system_id(system_id)
+            .component_id(component_id);
+        let frame = match version {
+            MavLinkVersion::V1 => builder
+                .version(V1)
+                .message(message)?
+                .build()
+                .into_versionless(),
+            MavLinkVersion::V2 => builder
+                .version(V2)
+                .message(message)?
+                .build()
+                .into_versionless(),
+        };
+
+        Ok(frame.into_mav_frame())
+    }
+
+    fn heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            // checked by `Config::check`
+            type_: MavType::try_from(self.mav_type).unwrap_or_default(),
+            autopilot: MavAutopilot::Invalid,
+            system_status: MavState::Active,
+            mavlink_version: MAVLINK_VERSION,
+            ..Default::default()
+        }
+    }
+}
+
+/// Periodically sends the plugin's `HEARTBEAT` to the connections with `heartbeat` enabled, so the plugin is seen
+/// as a MAVLink component.
+pub async fn run_heartbeat(router: Router, interval: Duration) {
+    let heartbeat = router.component.heartbeat();
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let config = router.config();
+        for connection in config.mavlink_connections.iter().filter(|c| c.heartbeat) {
+            let endpoint = &connection.endpoint;
+            if let Err(e) = router.send_to(endpoint, &heartbeat) {
+                error!("failed to send heartbeat to {endpoint}: {e}");
+            } else {
+                trace!("sent heartbeat to {endpoint}");
+            }
+        }
+    }
+}
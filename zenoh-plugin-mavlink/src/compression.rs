@@ -0,0 +1,79 @@
+//! Compression of the payloads published on Zenoh, for links billed by the byte.
+//!
+//! A compressed sample carries the name of its algorithm (`zstd` or `lz4`) as attachment. Samples received on
+//! the `in` key expression with such an attachment are decompressed before being split into frames.
+//! Single frames barely compress: compression pays off on batches (see [`crate::batch`]).
+
+use std::io;
+
+use serde::Deserialize;
+
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+pub const DEFAULT_COMPRESSION_MIN_SIZE: usize = 64;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionAlgorithm {
+    Zstd,
+    Lz4,
+}
+
+impl CompressionAlgorithm {
+    /// Name of the algorithm, carried as attachment of compressed samples.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"zstd" => Some(CompressionAlgorithm::Zstd),
+            b"lz4" => Some(CompressionAlgorithm::Lz4),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub algorithm: CompressionAlgorithm,
+    /// zstd compression level (1-22), ignored by lz4.
+    #[serde(default = "default_level")]
+    pub level: i32,
+    /// Payloads smaller than this size (in bytes) are published uncompressed.
+    #[serde(default = "default_min_size")]
+    pub min_size: usize,
+}
+
+fn default_level() -> i32 {
+    DEFAULT_ZSTD_LEVEL
+}
+
+fn default_min_size() -> usize {
+    DEFAULT_COMPRESSION_MIN_SIZE
+}
+
+impl CompressionConfig {
+    /// Compresses `payload`, or returns `None` if it is too small or does not shrink.
+    pub fn compress(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload.len() < self.min_size {
+            return None;
+        }
+        let compressed = match self.algorithm {
+            CompressionAlgorithm::Zstd => zstd::encode_all(payload, self.level).ok()?,
+            CompressionAlgorithm::Lz4 => lz4_flex::compress_prepend_size(payload),
+        };
+        (compressed.len() < payload.len()).then_some(compressed)
+    }
+}
+
+pub fn decompress(algorithm: CompressionAlgorithm, payload: &[u8]) -> io::Result<Vec<u8>> {
+    match algorithm {
+        CompressionAlgorithm::Zstd => zstd::decode_all(payload),
+        CompressionAlgorithm::Lz4 => lz4_flex::decompress_size_prepended(payload)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
@@ -1,13 +1,39 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use mavio::dialects::common::enums::MavType;
 use serde::Deserialize;
 
-use crate::mavlink_connection::MAVLinkConnection;
+use crate::{
+    access::AccessConfig,
+    batch::BatchConfig,
+    compression::{CompressionAlgorithm, CompressionConfig},
+    health::HealthConfig,
+    mavlink_connection::MAVLinkConnection,
+    qos::QosRule,
+    recorder::RecorderConfig,
+    runtime::SerialRuntimeConfig,
+    stream_rates::StreamRates,
+};
 
 pub const DEFAULT_NODENAME: &str = "zenoh_bridge_mavlink";
 pub const DEFAULT_WORK_THREAD_NUM: usize = 2;
 pub const DEFAULT_MAX_BLOCK_THREAD_NUM: usize = 50;
 pub const DEFAULT_BROADCAST_CHANNEL_CAPACITY: usize = 16384;
+/// Same system id as most ground control stations.
+pub const DEFAULT_SYSTEM_ID: u8 = 255;
+/// `MAV_COMP_ID_MISSIONPLANNER`
+pub const DEFAULT_COMPONENT_ID: u8 = 190;
+/// `MAV_TYPE_GCS`
+pub const DEFAULT_MAV_TYPE: u8 = 6;
+pub const DEFAULT_HEARTBEAT_INTERVAL: f32 = 1.0;
+pub const DEFAULT_STATS_INTERVAL: f32 = 5.0;
+/// Shortest period (in seconds) of the periodic tasks, rounding to a zero `Duration` below.
+pub const MIN_INTERVAL: f32 = 0.001;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default)]
@@ -18,10 +44,178 @@ pub struct Config {
     pub to_zenoh: bool,
     #[serde(default)]
     pub from_zenoh: bool,
+    /// Authorization and audit of the frames injected from zenoh, and of the commands requested via zenoh.
+    #[serde(default)]
+    pub from_zenoh_access: AccessConfig,
     #[serde(default = "default_work_thread_num")]
     pub work_thread_num: usize,
     #[serde(default = "default_max_block_thread_num")]
     pub max_block_thread_num: usize,
+    /// Run the serial connections on a dedicated single-threaded runtime.
+    #[serde(default)]
+    pub serial_runtime: Option<SerialRuntimeConfig>,
+    #[serde(default = "default_system_id")]
+    pub system_id: u8,
+    #[serde(default = "default_component_id")]
+    pub component_id: u8,
+    #[serde(default = "default_mav_type")]
+    pub mav_type: u8,
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: f32,
+    /// Period (in seconds) of the connection statistics published on zenoh, 0 to disable.
+    #[serde(default = "default_stats_interval")]
+    pub stats_interval: f32,
+    /// Liveliness monitoring and reopening of the connections.
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub vehicle_stream_rates: HashMap<u8, StreamRates>,
+    #[serde(default)]
+    pub recorder: Option<RecorderConfig>,
+    /// Message id => number of frames kept per system/component for late-joining subscribers.
+    #[serde(default)]
+    pub to_zenoh_history: HashMap<u32, usize>,
+    /// Zenoh priority, congestion control and express mode of the published frames, per message.
+    #[serde(default)]
+    pub to_zenoh_qos: Vec<QosRule>,
+    /// Pack the frames published on zenoh in batches.
+    #[serde(default)]
+    pub to_zenoh_batch: Option<BatchConfig>,
+    /// Compress the payloads published on zenoh.
+    #[serde(default)]
+    pub to_zenoh_compression: Option<CompressionConfig>,
+    /// Publish decoded messages as JSON instead of raw frames.
+    #[serde(default)]
+    pub to_zenoh_json: bool,
+}
+
+impl Config {
+    /// Checks the connections and the settings the plugin would only reject (or misbehave with) once running.
+    /// Returns the problems found. The plugin does not start with any.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut endpoints = HashSet::new();
+        for connection in &self.mavlink_connections {
+            if !endpoints.insert(&connection.endpoint) {
+                problems.push(format!(
+                    "{}: endpoint used by several connections",
+                    connection.endpoint
+                ));
+            }
+            for problem in connection.check() {
+                problems.push(format!("{}: {problem}", connection.endpoint));
+            }
+        }
+
+        if self.broadcast_channel_capacity == 0 {
+            problems.push("broadcast_channel_capacity must be greater than 0".to_string());
+        }
+        if self.work_thread_num == 0 {
+            problems.push("work_thread_num must be greater than 0".to_string());
+        }
+        if self.max_block_thread_num == 0 {
+            problems.push("max_block_thread_num must be greater than 0".to_string());
+        }
+        if let Some(cpu) = self.serial_runtime.as_ref().and_then(|r| r.cpu) {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+            if cpu >= cpus {
+                problems.push(format!(
+                    "serial_runtime: no CPU {cpu} (expecting 0-{})",
+                    cpus - 1
+                ));
+            }
+        }
+        if MavType::try_from(self.mav_type).is_err() {
+            problems.push(format!("invalid mav_type {}", self.mav_type));
+        }
+        if !is_interval(self.heartbeat_interval) {
+            problems.push(format!(
+                "heartbeat_interval must be at least {MIN_INTERVAL}"
+            ));
+        }
+        if self.stats_interval != 0.0 && !is_interval(self.stats_interval) {
+            problems.push(format!(
+                "stats_interval must be 0 or at least {MIN_INTERVAL}"
+            ));
+        }
+        if duration(self.health.timeout).is_none() {
+            problems.push(format!(
+                "health: invalid timeout {} (expecting a duration in seconds)",
+                self.health.timeout
+            ));
+        }
+        if duration(self.health.reconnect_delay).is_none() {
+            problems.push(format!(
+                "health: invalid reconnect_delay {} (expecting a duration in seconds)",
+                self.health.reconnect_delay
+            ));
+        }
+        for problem in self.from_zenoh_access.check() {
+            problems.push(format!("from_zenoh_access: {problem}"));
+        }
+
+        let mut qos_messages = HashSet::new();
+        for rule in &self.to_zenoh_qos {
+            for id in &rule.messages {
+                if !qos_messages.insert(id) {
+                    problems.push(format!("to_zenoh_qos: message {id} is in several rules"));
+                }
+            }
+        }
+        if let Some(batch) = &self.to_zenoh_batch {
+            if duration(batch.window).is_none() {
+                problems.push(format!(
+                    "to_zenoh_batch: invalid window {} (expecting a duration in seconds)",
+                    batch.window
+                ));
+            }
+            if batch.max_bytes == 0 {
+                problems.push("to_zenoh_batch: max_bytes must be greater than 0".to_string());
+            }
+        }
+        if let Some(max) = self.recorder.as_ref().and_then(|r| r.max_file_duration) {
+            if !(max > 0.0 && duration(max).is_some()) {
+                problems.push(format!(
+                    "recorder: invalid max_file_duration {max} (expecting a duration in seconds, greater than 0)"
+                ));
+            }
+        }
+        if let Some(compression) = &self.to_zenoh_compression {
+            if compression.algorithm == CompressionAlgorithm::Zstd
+                && !(1..=22).contains(&compression.level)
+            {
+                problems.push(format!(
+                    "to_zenoh_compression: invalid zstd level {} (expecting 1-22)",
+                    compression.level
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Checks that the endpoints are usable right now (see [`MAVLinkConnection::check_environment`]). Returns the
+    /// problems found, that may be transient (e.g. a serial device not plugged yet).
+    pub fn check_environment(&self) -> Vec<String> {
+        self.mavlink_connections
+            .iter()
+            .filter_map(|connection| {
+                connection
+                    .check_environment()
+                    .err()
+                    .map(|e| format!("{}: {e}", connection.endpoint))
+            })
+            .collect()
+    }
+}
+
+/// Returns `seconds` as a duration, or `None` if negative, not finite or too large.
+pub(crate) fn duration(seconds: f32) -> Option<Duration> {
+    Duration::try_from_secs_f32(seconds).ok()
+}
+
+/// Whether `seconds` is a usable period of a periodic task.
+fn is_interval(seconds: f32) -> bool {
+    seconds >= MIN_INTERVAL && duration(seconds).is_some()
 }
 
 fn broadcast_channel_capacity() -> usize {
@@ -35,3 +229,23 @@ fn default_work_thread_num() -> usize {
 fn default_max_block_thread_num() -> usize {
     DEFAULT_MAX_BLOCK_THREAD_NUM
 }
+
+fn default_system_id() -> u8 {
+    DEFAULT_SYSTEM_ID
+}
+
+fn default_component_id() -> u8 {
+    DEFAULT_COMPONENT_ID
+}
+
+fn default_mav_type() -> u8 {
+    DEFAULT_MAV_TYPE
+}
+
+fn default_heartbeat_interval() -> f32 {
+    DEFAULT_HEARTBEAT_INTERVAL
+}
+
+fn default_stats_interval() -> f32 {
+    DEFAULT_STATS_INTERVAL
+}
@@ -0,0 +1,76 @@
+//! Zenoh encodings of the samples published and accepted by the plugin.
+//!
+//! - `application/mavlink;v1`, `application/mavlink;v2`, `application/mavlink;signed`: one raw frame (of that kind).
+//!   On the `in` key expression, `application/mavlink` (whatever the schema) and untyped samples may carry
+//!   several raw frames back to back.
+//! - `application/mavlink;batch`: a batch of length-prefixed frames (see [`crate::batch`]).
+//! - `application/json`: a decoded message ([`JsonMessage`]).
+
+use mavio::dialects::common::Common;
+use serde::{Deserialize, Serialize};
+use zenoh::bytes::Encoding;
+
+use crate::protocol::{MAVLINK_IFLAG_SIGNED, MAVLINK_V1_STX};
+
+pub const MAVLINK_ENCODING: &str = "application/mavlink";
+
+/// Encoding of a raw frame.
+pub fn frame_encoding(frame: &[u8]) -> Encoding {
+    let schema = match frame {
+        [MAVLINK_V1_STX, ..] => "v1",
+        [_, _, incompat_flags, ..] if incompat_flags & MAVLINK_IFLAG_SIGNED != 0 => "signed",
+        _ => "v2",
+    };
+    mavlink_encoding(schema)
+}
+
+/// Encoding of the samples carrying a batch of frames.
+pub fn batch_encoding() -> Encoding {
+    mavlink_encoding("batch")
+}
+
+/// `application/mavlink` with `schema`. Zenoh has no predefined `application/mavlink` encoding: parsed alone, the
+/// prefix would be kept as the schema of `zenoh/bytes`, and replaced by [`Encoding::with_schema`].
+fn mavlink_encoding(schema: &str) -> Encoding {
+    Encoding::from(format!("{MAVLINK_ENCODING};{schema}"))
+}
+
+/// Format of a sample received from zenoh, told by its encoding.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Frames,
+    Batch,
+    Json,
+}
+
+impl Format {
+    /// Returns `None` for encodings the plugin does not accept.
+    pub fn of(encoding: &Encoding) -> Option<Self> {
+        if encoding == &Encoding::ZENOH_BYTES || encoding == &Encoding::APPLICATION_OCTET_STREAM {
+            return Some(Format::Frames);
+        }
+        if encoding == &Encoding::APPLICATION_JSON {
+            return Some(Format::Json);
+        }
+        let encoding = encoding.to_string();
+        let (prefix, schema) = encoding.split_once(';').unwrap_or((&encoding, ""));
+        match (prefix, schema) {
+            (MAVLINK_ENCODING, "batch") => Some(Format::Batch),
+            (MAVLINK_ENCODING, _) => Some(Format::Frames),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded message, e.g. `{"system_id":1,"component_id":1,"message":{"Heartbeat":{...}}}`.
+///
+/// Messages received from zenoh without `system_id`/`component_id` are sent with the plugin's identity.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct JsonMessage {
+    #[serde(default)]
+    pub system_id: Option<u8>,
+    #[serde(default)]
+    pub component_id: Option<u8>,
+    pub message: Common,
+}
@@ -0,0 +1,36 @@
+//! Selection of the messages exchanged on a MAVLink connection.
+
+use std::collections::BTreeSet;
+
+use mavio::{protocol::MavLinkVersion, MavFrame};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Default, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MessageFilter {
+    /// Only these message ids are exchanged (every message if empty).
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub allow: BTreeSet<u32>,
+    /// These message ids are never exchanged.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub deny: BTreeSet<u32>,
+}
+
+impl MessageFilter {
+    pub fn allows(&self, message_id: u32) -> bool {
+        (self.allow.is_empty() || self.allow.contains(&message_id))
+            && !self.deny.contains(&message_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+/// Returns whether `frame` is of the given MAVLink version (1 or 2).
+pub fn is_version(frame: &MavFrame, version: u8) -> bool {
+    match frame.version() {
+        MavLinkVersion::V1 => version == 1,
+        MavLinkVersion::V2 => version == 2,
+    }
+}
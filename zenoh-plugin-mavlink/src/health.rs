@@ -0,0 +1,293 @@
+//! Health of the MAVLink connections.
+//!
+//! A connection is healthy when it is connected, received a frame in the last `timeout` seconds (its
+//! `health_timeout`, or the plugin's `health.timeout`) and was not reopened more than `health.max_reconnects`
+//! times in the last [`RECONNECT_WINDOW`]. Failed connections are reopened after `health.reconnect_delay`
+//! seconds. Connections becoming unhealthy (or healthy again) are logged, and the status is served as JSON
+//! ([`HealthStatus`]) by a queryable on `@/<zenoh_id>/@mavlink/v2/health`. It is also in the admin space, under
+//! `@/<zenoh_id>/router/status/plugins/mavlink/health` (or the bridge's equivalent).
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use zenoh::{bytes::Encoding, key_expr::format::keformat, Session};
+
+use crate::liveliness::ke_liveliness_health;
+
+pub const DEFAULT_HEALTH_TIMEOUT: f32 = 5.0;
+pub const DEFAULT_RECONNECT_DELAY: f32 = 1.0;
+pub const DEFAULT_MAX_RECONNECTS: usize = 3;
+/// Reopenings of a connection are counted over this window.
+pub const RECONNECT_WINDOW: Duration = Duration::from_secs(60);
+/// Period of the check logging the connections becoming unhealthy.
+pub const CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    /// A connection is unhealthy when no frame was received for this time (in seconds), 0 to disable.
+    #[serde(default = "default_timeout")]
+    pub timeout: f32,
+    /// Time (in seconds) before reopening a connection that failed.
+    #[serde(default = "default_reconnect_delay")]
+    pub reconnect_delay: f32,
+    /// A connection reopened more often than this in [`RECONNECT_WINDOW`] is unhealthy.
+    #[serde(default = "default_max_reconnects")]
+    pub max_reconnects: usize,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_timeout(),
+            reconnect_delay: default_reconnect_delay(),
+            max_reconnects: default_max_reconnects(),
+        }
+    }
+}
+
+fn default_timeout() -> f32 {
+    DEFAULT_HEALTH_TIMEOUT
+}
+
+fn default_reconnect_delay() -> f32 {
+    DEFAULT_RECONNECT_DELAY
+}
+
+fn default_max_reconnects() -> usize {
+    DEFAULT_MAX_RECONNECTS
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConnectionHealth {
+    pub endpoint: String,
+    pub state: ConnectionState,
+    pub healthy: bool,
+    /// Seconds since the last frame received, if any.
+    pub last_frame: Option<f64>,
+    /// Reopenings in the last [`RECONNECT_WINDOW`].
+    pub reconnects: usize,
+    /// Why the connection is unhealthy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthStatus {
+    /// Whether every connection is healthy.
+    pub healthy: bool,
+    pub connections: Vec<ConnectionHealth>,
+}
+
+struct ConnectionRecord {
+    state: ConnectionState,
+    /// When the connection was (re)opened.
+    opened: Instant,
+    last_frame: Option<Instant>,
+    timeout: Duration,
+    reconnects: VecDeque<Instant>,
+    healthy: bool,
+}
+
+impl ConnectionRecord {
+    fn health(&self, endpoint: &str, max_reconnects: usize, now: Instant) -> ConnectionHealth {
+        // frames received before the connection was reopened do not count
+        let last_frame = self.last_frame.map_or(self.opened, |t| t.max(self.opened));
+        let silence = now.duration_since(last_frame);
+        let reconnects = self
+            .reconnects
+            .iter()
+            .filter(|t| now.duration_since(**t) < RECONNECT_WINDOW)
+            .count();
+        let problem = if self.state != ConnectionState::Connected {
+            Some("not connected".to_string())
+        } else if !self.timeout.is_zero() && silence > self.timeout {
+            Some(format!(
+                "no frame received for {:.1} seconds",
+                silence.as_secs_f64()
+            ))
+        } else if reconnects > max_reconnects {
+            Some(format!(
+                "reopened {reconnects} times in the last {} seconds",
+                RECONNECT_WINDOW.as_secs()
+            ))
+        } else {
+            None
+        };
+        ConnectionHealth {
+            endpoint: endpoint.to_string(),
+            state: self.state,
+            healthy: problem.is_none(),
+            last_frame: self.last_frame.map(|t| now.duration_since(t).as_secs_f64()),
+            reconnects,
+            problem,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    config: HealthConfig,
+    connections: BTreeMap<String, ConnectionRecord>,
+}
+
+/// Health of every connection, shared between the connections, the queryable and the admin space.
+#[derive(Clone, Default)]
+pub struct Health {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Health {
+    pub fn new(config: &HealthConfig) -> Self {
+        let health = Self::default();
+        health.inner.lock().unwrap().config = config.clone();
+        health
+    }
+
+    pub fn reconnect_delay(&self) -> Duration {
+        Duration::from_secs_f32(self.inner.lock().unwrap().config.reconnect_delay)
+    }
+
+    /// Records that `endpoint` is being (re)opened, `timeout` overriding the plugin's one.
+    pub fn connecting(&self, endpoint: &str, timeout: Option<f32>) {
+        let mut inner = self.inner.lock().unwrap();
+        let timeout = Duration::from_secs_f32(timeout.unwrap_or(inner.config.timeout));
+        let now = Instant::now();
+        match inner.connections.get_mut(endpoint) {
+            Some(record) => {
+                record.reconnects.push_back(now);
+                while record
+                    .reconnects
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) >= RECONNECT_WINDOW)
+                {
+                    record.reconnects.pop_front();
+                }
+                record.state = ConnectionState::Connecting;
+                record.opened = now;
+                record.timeout = timeout;
+            }
+            None => {
+                inner.connections.insert(
+                    endpoint.to_string(),
+                    ConnectionRecord {
+                        state: ConnectionState::Connecting,
+                        opened: now,
+                        last_frame: None,
+                        timeout,
+                        reconnects: VecDeque::new(),
+                        healthy: true,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn connected(&self, endpoint: &str) {
+        self.set_state(endpoint, ConnectionState::Connected);
+    }
+
+    pub fn disconnected(&self, endpoint: &str) {
+        self.set_state(endpoint, ConnectionState::Disconnected);
+    }
+
+    fn set_state(&self, endpoint: &str, state: ConnectionState) {
+        if let Some(record) = self.inner.lock().unwrap().connections.get_mut(endpoint) {
+            record.state = state;
+            record.opened = Instant::now();
+        }
+    }
+
+    /// Records a frame received on `endpoint`.
+    pub fn received(&self, endpoint: &str) {
+        if let Some(record) = self.inner.lock().unwrap().connections.get_mut(endpoint) {
+            record.last_frame = Some(Instant::now());
+        }
+    }
+
+    /// Forgets a closed connection.
+    pub fn remove(&self, endpoint: &str) {
+        self.inner.lock().unwrap().connections.remove(endpoint);
+    }
+
+    pub fn status(&self) -> HealthStatus {
+        let inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let connections: Vec<ConnectionHealth> = inner
+            .connections
+            .iter()
+            .map(|(endpoint, record)| record.health(endpoint, inner.config.max_reconnects, now))
+            .collect();
+        HealthStatus {
+            healthy: connections.iter().all(|c| c.healthy),
+            connections,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self.status()).unwrap_or_default()
+    }
+
+    /// Logs the connections that became unhealthy, or healthy again.
+    fn check(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let max_reconnects = inner.config.max_reconnects;
+        for (endpoint, record) in inner.connections.iter_mut() {
+            let health = record.health(endpoint, max_reconnects, now);
+            if health.healthy != record.healthy {
+                record.healthy = health.healthy;
+                match health.problem {
+                    Some(problem) => warn!("connection {endpoint} is unhealthy: {problem}"),
+                    None => info!("connection {endpoint} is healthy again"),
+                }
+            }
+        }
+    }
+
+    /// Serves the health status on zenoh, and periodically logs the connections becoming unhealthy.
+    pub async fn run(self, zsession: Arc<Session>) {
+        let ke = keformat!(
+            ke_liveliness_health::formatter(),
+            zenoh_id = zsession.zid().into_keyexpr()
+        )
+        .unwrap();
+        let queryable = match zsession.declare_queryable(ke.clone()).await {
+            Ok(queryable) => queryable,
+            Err(e) => {
+                error!("unable to declare health queryable on {ke}: {e}");
+                return;
+            }
+        };
+        info!("serving connections health on {ke}");
+
+        let mut interval = tokio::time::interval(CHECK_PERIOD);
+        loop {
+            tokio::select! {
+                query = queryable.recv_async() => {
+                    let Ok(query) = query else { return };
+                    let json = self.to_json().to_string();
+                    let reply = query.reply(ke.clone(), json).encoding(Encoding::APPLICATION_JSON);
+                    if let Err(e) = reply.await {
+                        error!("failed to reply to health query: {e}");
+                    }
+                }
+                _ = interval.tick() => self.check(),
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Keeps the last frames of selected messages for late-joining Zenoh subscribers.
+//!
+//! The frames of the messages configured in `to_zenoh_history` are kept, per system/component, as they are
+//! published on the `out` key expression. Like a zenoh-ext `PublicationCache` (that only keeps the last samples of
+//! each key expression, whatever their message), a queryable on `@/<zenoh_id>/@mavlink/v2/out` replies with them:
+//! a subscriber joining late gets the recent state with a query on `@/*/@mavlink/v2/out` (or a querying subscriber
+//! on that key expression).
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tracing::{debug, error, info};
+use zenoh::{
+    bytes::Encoding,
+    key_expr::{format::keformat, OwnedKeyExpr},
+    query::Query,
+    Session,
+};
+
+use crate::{liveliness::ke_liveliness_pub, protocol::Protocol};
+
+/// Frames kept (with their encoding), by message id and system/component id, oldest first.
+type Frames = BTreeMap<(u32, u8, u8), VecDeque<(Vec<u8>, Encoding)>>;
+
+#[derive(Clone, Default)]
+pub(crate) struct History {
+    /// Message id => number of frames kept per system/component.
+    depths: Arc<HashMap<u32, usize>>,
+    frames: Arc<Mutex<Frames>>,
+}
+
+impl History {
+    /// Keeps the frames of each message id => history depth of `depths`.
+    pub fn new(depths: &HashMap<u32, usize>) -> Self {
+        let depths = depths
+            .iter()
+            .filter(|(_, depth)| **depth > 0)
+            .map(|(message_id, depth)| (*message_id, *depth))
+            .collect();
+        Self {
+            depths: Arc::new(depths),
+            frames: Default::default(),
+        }
+    }
+
+    /// Whether no message has a history.
+    pub fn is_empty(&self) -> bool {
+        self.depths.is_empty()
+    }
+
+    /// Keeps `payload`, published with `encoding` for `msg`, if its message has a history.
+    pub fn keep(&self, msg: &Protocol, payload: &[u8], encoding: &Encoding) {
+        let message_id = msg.mav_frame.message_id();
+        let Some(depth) = self.depths.get(&message_id) else {
+            return;
+        };
+        let mut frames = self.frames.lock().unwrap();
+        let frames = frames
+            .entry((
+                message_id,
+                msg.mav_frame.system_id(),
+                msg.mav_frame.component_id(),
+            ))
+            .or_default();
+        if frames.len() >= *depth {
+            frames.pop_front();
+        }
+        frames.push_back((payload.to_vec(), encoding.clone()));
+    }
+
+    /// Serves the frames kept on the history queryable, apart from the publication so that slow queriers do not
+    /// delay it.
+    pub async fn run(self, zsession: Arc<Session>) {
+        let ke = keformat!(
+            ke_liveliness_pub::formatter(),
+            zenoh_id = zsession.zid().into_keyexpr()
+        )
+        .unwrap();
+        let queryable = match zsession.declare_queryable(ke.clone()).await {
+            Ok(queryable) => queryable,
+            Err(e) => {
+                error!("unable to declare history queryable on {ke}: {e}");
+                return;
+            }
+        };
+
+        info!(
+            "keeping the last frames of messages {:?} on {ke}",
+            self.depths
+        );
+        while let Ok(query) = queryable.recv_async().await {
+            self.reply(&ke, query).await;
+        }
+    }
+
+    /// Replies to `query` with every frame kept.
+    async fn reply(&self, ke: &OwnedKeyExpr, query: Query) {
+        let frames: Vec<_> = self
+            .frames
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        for (payload, encoding) in frames {
+            if let Err(e) = query.reply(ke.clone(), payload).encoding(encoding).await {
+                error!("failed to reply to history query: {e}");
+                return;
+            }
+        }
+        debug!("replied to history query {}", query.selector());
+    }
+}
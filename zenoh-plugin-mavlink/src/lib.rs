@@ -1,60 +1,99 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+use access::Access;
+use batch::{split_batch, Batcher};
+use command::CommandService;
+use component::{run_heartbeat, Component};
+use compression::{decompress, CompressionAlgorithm, CompressionConfig};
+use encoding::{batch_encoding, frame_encoding, Format, JsonMessage};
+use health::Health;
+use history::History;
 use liveliness::{ke_liveliness_plugin, ke_liveliness_pub, ke_liveliness_sub};
-use mavio::MavFrame;
-use protocol::Protocol;
-use tokio::task::{JoinHandle, JoinSet};
-use tracing::{debug, debug_span, error, info};
+use mavio::{dialects::common::Common, MavFrame};
+use mavlink_connection::MAVLinkConnection;
+use mission::MissionService;
+use params::ParamService;
+use protocol::{parse_raw_frame, split_raw_frames, Protocol, ZENOH_ORIGIN};
+use qos::Publishers;
+use recorder::Recorder;
+use routing::{Router, RoutingTable};
+use runtime::SerialRuntime;
+use stats::Statistics;
+use stream_rates::StreamRateService;
+use tokio::select;
+use tokio::sync::broadcast::{error::RecvError, Receiver, Sender};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, watch};
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, debug_span, error, info, warn};
 use tracing::{info_span, Instrument};
-use zenoh::bytes::ZBytes;
+use zenoh::bytes::{Encoding, ZBytes};
+use zenoh::pubsub::Publisher;
 use zenoh::{
     internal::{
-        plugins::{RunningPlugin, RunningPluginTrait, ZenohPlugin},
+        plugins::{Response, RunningPlugin, RunningPluginTrait, ZenohPlugin},
         runtime::Runtime,
         zerror,
     },
-    key_expr::format::keformat,
+    key_expr::{format::keformat, keyexpr, KeyExpr},
     liveliness::LivelinessToken,
     Result as ZResult, Session,
 };
 use zenoh_plugin_trait::{plugin_long_version, plugin_version, Plugin, PluginControl};
 
+pub mod access;
+pub mod batch;
+pub mod command;
+pub mod component;
+pub mod compression;
 pub mod config;
+pub mod encoding;
+pub mod filter;
+pub mod health;
+mod history;
 pub mod liveliness;
 pub mod mavlink_connection;
+pub mod mission;
+pub mod params;
 pub mod protocol;
+pub mod qos;
+pub mod recorder;
+pub mod replay;
+pub mod routing;
+pub mod runtime;
+mod service;
+pub mod sim;
+pub mod stats;
+pub mod stream_rates;
 use config::Config;
 
 lazy_static::lazy_static! {
     static ref WORK_THREAD_NUM: AtomicUsize = AtomicUsize::new(config::DEFAULT_WORK_THREAD_NUM);
     static ref MAX_BLOCK_THREAD_NUM: AtomicUsize = AtomicUsize::new(config::DEFAULT_MAX_BLOCK_THREAD_NUM);
-    // The global runtime is used in the dynamic plugins, which we can't get the current runtime
+    // The plugin's runtime, in the dynamic plugins as in the standalone bridge, so that its settings always apply
     static ref TOKIO_RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
                .worker_threads(WORK_THREAD_NUM.load(Ordering::SeqCst))
                .max_blocking_threads(MAX_BLOCK_THREAD_NUM.load(Ordering::SeqCst))
+               .thread_name(runtime::WORKER_THREAD_NAME)
                .enable_all()
                .build()
                .expect("Unable to create runtime");
 }
+
+/// Spawns a task on the plugin's runtime (e.g. to monitor it). The runtime is created on first use, with the
+/// thread settings of the plugin once it is started.
 #[inline(always)]
-pub(crate) fn spawn_runtime<F>(task: F) -> JoinHandle<F::Output>
+pub fn spawn_runtime<F>(task: F) -> JoinHandle<F::Output>
 where
     F: Future + Send + 'static,
     F::Output: Send + 'static,
 {
-    // Check whether able to get the current runtime
-    match tokio::runtime::Handle::try_current() {
-        Ok(rt) => {
-            // Able to get the current runtime (standalone binary), spawn on the current runtime
-            rt.spawn(task)
-        }
-        Err(_) => {
-            // Unable to get the current runtime (dynamic plugins), spawn on the global runtime
-            TOKIO_RUNTIME.spawn(task)
-        }
-    }
+    TOKIO_RUNTIME.spawn(task)
 }
 
 pub struct MAVLinkPlugin;
@@ -82,20 +121,96 @@ impl Plugin for MAVLinkPlugin {
         info!("{:?}", plugin_conf.clone());
         let config: Config = serde_json::from_value(plugin_conf.clone())
             .map_err(|e| zerror!("Plugin `{}` configuration error: {}", name, e))?;
+        let problems = config.check();
+        if !problems.is_empty() {
+            return Err(zerror!(
+                "Plugin `{}` configuration error: {}",
+                name,
+                problems.join(", ")
+            )
+            .into());
+        }
         WORK_THREAD_NUM.store(config.work_thread_num, Ordering::SeqCst);
         MAX_BLOCK_THREAD_NUM.store(config.max_block_thread_num, Ordering::SeqCst);
 
-        spawn_runtime(run(runtime.clone(), config));
-        Ok(Box::new(MAVLinkPlugin))
+        let statistics = Statistics::default();
+        let health = Health::new(&config.health);
+        let (reloads, reloads_rx) = unbounded_channel();
+        spawn_runtime(run(
+            runtime.clone(),
+            config,
+            statistics.clone(),
+            health.clone(),
+            reloads_rx,
+        ));
+        Ok(Box::new(RunningMAVLinkPlugin {
+            statistics,
+            health,
+            reloads,
+        }))
     }
 }
 
-impl RunningPluginTrait for MAVLinkPlugin {}
+/// The running plugin, serving its status in the admin space and applying configuration changes.
+struct RunningMAVLinkPlugin {
+    statistics: Statistics,
+    health: Health,
+    reloads: UnboundedSender<Config>,
+}
+
+impl PluginControl for RunningMAVLinkPlugin {}
+
+impl RunningPluginTrait for RunningMAVLinkPlugin {
+    fn config_checker(
+        &self,
+        _path: &str,
+        _current: &serde_json::Map<String, serde_json::Value>,
+        new: &serde_json::Map<String, serde_json::Value>,
+    ) -> ZResult<Option<serde_json::Map<String, serde_json::Value>>> {
+        let config: Config = serde_json::from_value(serde_json::Value::Object(new.clone()))
+            .map_err(|e| zerror!("MAVLink plugin configuration error: {e}"))?;
+        let problems = config.check();
+        if !problems.is_empty() {
+            return Err(zerror!(
+                "MAVLink plugin configuration error: {}",
+                problems.join(", ")
+            )
+            .into());
+        }
+        self.reloads
+            .send(config)
+            .map_err(|_| zerror!("MAVLink plugin is not running"))?;
+        Ok(None)
+    }
+
+    fn adminspace_getter<'a>(
+        &'a self,
+        key_expr: &'a KeyExpr<'a>,
+        plugin_status_key: &str,
+    ) -> ZResult<Vec<Response>> {
+        let mut responses = Vec::new();
+        let stats_key = format!("{plugin_status_key}/stats");
+        if key_expr.intersects(keyexpr::new(&stats_key)?) {
+            responses.push(Response::new(stats_key, self.statistics.to_json()));
+        }
+        let health_key = format!("{plugin_status_key}/health");
+        if key_expr.intersects(keyexpr::new(&health_key)?) {
+            responses.push(Response::new(health_key, self.health.to_json()));
+        }
+        Ok(responses)
+    }
+}
 
 #[cfg(feature = "dynamic_plugin")]
 zenoh_plugin_trait::declare_plugin!(MAVLinkPlugin);
 
-pub async fn run(runtime: Runtime, config: Config) {
+pub async fn run(
+    runtime: Runtime,
+    config: Config,
+    statistics: Statistics,
+    health: Health,
+    mut reloads: UnboundedReceiver<Config>,
+) {
     debug!(
         "Zenoh MAVLink plugin {}",
         MAVLinkPlugin::PLUGIN_LONG_VERSION
@@ -111,121 +226,541 @@ pub async fn run(runtime: Runtime, config: Config) {
         }
     };
 
-    // Declare plugin's liveliness token
-    let ke_liveliness = keformat!(
-        ke_liveliness_plugin::formatter(),
-        zenoh_id = zsession.zid().into_keyexpr()
-    )
-    .unwrap();
-    let member = match zsession.liveliness().declare_token(ke_liveliness).await {
-        Ok(member) => member,
+    let mav_plugin = match MAVLinkPluginRuntime::new(zsession, config, statistics, health).await {
+        Ok(mav_plugin) => mav_plugin,
         Err(e) => {
-            tracing::error!(
-                "Unable to declare liveliness token for MAVLink plugin : {:?}",
-                e
-            );
+            error!("Unable to start MAVLink plugin: {e:?}");
             return;
         }
     };
 
-    let mav_plugin = MAVLinkPluginRuntime {
-        config: Arc::new(config),
-        zsession,
-        _member: member,
-    };
-
-    mav_plugin.run().await;
+    select! {
+        _ = mav_plugin.run() => {}
+        _ = async {
+            while let Some(config) = reloads.recv().await {
+                mav_plugin.reload(config);
+            }
+        } => {}
+    }
 }
 
-pub(crate) struct MAVLinkPluginRuntime {
-    config: Arc<Config>,
+/// The MAVLink plugin running on a Zenoh session.
+/// Besides the plugin itself, it can be started on any session (e.g. for integration tests).
+pub struct MAVLinkPluginRuntime {
+    config: watch::Sender<Arc<Config>>,
     zsession: Arc<Session>,
+    statistics: Statistics,
+    health: Health,
+    /// Authorization of the frames and commands coming from zenoh.
+    access: Arc<Access>,
+    /// Runtime of the serial connections, if they have a dedicated one.
+    serial_runtime: Option<SerialRuntime>,
     _member: LivelinessToken,
 }
 
 impl MAVLinkPluginRuntime {
-    async fn run(&self) {
+    pub async fn new(
+        zsession: Arc<Session>,
+        config: Config,
+        statistics: Statistics,
+        health: Health,
+    ) -> ZResult<Self> {
+        let problems = config.check();
+        if !problems.is_empty() {
+            return Err(zerror!(
+                "Invalid MAVLink plugin configuration: {}",
+                problems.join(", ")
+            )
+            .into());
+        }
+
+        // Declare plugin's liveliness token
+        let ke_liveliness = keformat!(
+            ke_liveliness_plugin::formatter(),
+            zenoh_id = zsession.zid().into_keyexpr()
+        )
+        .unwrap();
+        let member = zsession
+            .liveliness()
+            .declare_token(ke_liveliness)
+            .await
+            .map_err(|e| zerror!("Unable to declare liveliness token for MAVLink plugin: {e}"))?;
+
+        let access = Arc::new(Access::new(&config.from_zenoh_access));
+
+        let serial_runtime = match &config.serial_runtime {
+            Some(serial_runtime) => {
+                info!("starting dedicated runtime for serial connections");
+                Some(SerialRuntime::start(serial_runtime).map_err(|e| {
+                    zerror!("Unable to start the runtime of the serial connections: {e}")
+                })?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            config: watch::Sender::new(Arc::new(config)),
+            zsession,
+            statistics,
+            health,
+            access,
+            serial_runtime,
+            _member: member,
+        })
+    }
+
+    /// Applies a new configuration to the running plugin. MAVLink connections are opened, closed or updated
+    /// (filters, versions, heartbeats and stream rates are applied without reopening them), and vehicle
+    /// stream rates are requested again. The other settings are only applied on restart.
+    pub fn reload(&self, config: Config) {
+        let current = self.config.borrow().clone();
+        let reloaded = Config {
+            mavlink_connections: config.mavlink_connections.clone(),
+            vehicle_stream_rates: config.vehicle_stream_rates.clone(),
+            ..(*current).clone()
+        };
+        if reloaded != config {
+            warn!("only mavlink_connections and vehicle_stream_rates are reloaded, restart to apply the other settings");
+        }
+        if reloaded == *current {
+            debug!("configuration unchanged");
+            return;
+        }
+        let problems = reloaded.check();
+        if !problems.is_empty() {
+            error!(
+                "configuration not reloaded, invalid MAVLink plugin configuration: {}",
+                problems.join(", ")
+            );
+            return;
+        }
+        info!("reloading configuration");
+        self.config.send_replace(Arc::new(reloaded));
+    }
+
+    /// Run the plugin, applying configuration [reloads](Self::reload). It keeps running when every MAVLink
+    /// connection is closed, as connections may be added by a reload.
+    pub async fn run(&self) {
+        let config = self.config.borrow().clone();
+        let mut config_updates = self.config.subscribe();
+
         // spawn broadcast channel
         let (tx, rx) =
-            tokio::sync::broadcast::channel::<Protocol>(self.config.broadcast_channel_capacity);
+            tokio::sync::broadcast::channel::<Protocol>(config.broadcast_channel_capacity);
+        // dropped when the plugin stops, for the tasks to wind down
+        let (_stop, stop) = oneshot::channel::<()>();
 
         // spawn task for each mavlink connection
         let mut set = JoinSet::new();
-        for mav_conn in self.config.mavlink_connections.clone() {
-            info!("spawning task for {mav_conn:?}");
-            set.spawn(mav_conn.handle((tx.clone(), rx.resubscribe())));
+        let mut connections = HashMap::new();
+        for mav_conn in config.mavlink_connections.clone() {
+            let endpoint = mav_conn.endpoint.clone();
+            let connection = self.spawn_connection(&mut set, mav_conn, &tx);
+            connections.insert(endpoint, connection);
+        }
+
+        // launch task to publish the statistics of the connections
+        if config.stats_interval > 0.0 {
+            info!("spawning statistics task");
+            tokio::spawn(self.statistics.clone().run(
+                self.zsession.clone(),
+                Duration::from_secs_f32(config.stats_interval),
+            ));
+        }
+
+        // launch task to serve the health of the connections
+        info!("spawning health task");
+        tokio::spawn(self.health.clone().run(self.zsession.clone()));
+
+        // launch thread to record every frame to tlog files
+        if let Some(recorder) = config.recorder.clone() {
+            info!("spawning recorder task");
+            let rx = rx.resubscribe();
+            tokio::task::spawn_blocking(move || Recorder::new(recorder).run(rx));
         }
 
+        // keep track of the connection each remote system is reachable through
+        let routes = RoutingTable::default();
+        tokio::spawn(routes.clone().run(rx.resubscribe()));
+        let router = Router::new(
+            Arc::new(Component::from(config.as_ref())),
+            routes,
+            self.config.subscribe(),
+            tx.clone(),
+        );
+
+        // launch task to announce the plugin as a MAVLink component
+        info!("spawning heartbeat task");
+        tokio::spawn(run_heartbeat(
+            router.clone(),
+            Duration::from_secs_f32(config.heartbeat_interval),
+        ));
+
+        // launch task to request telemetry streams from vehicles
+        info!("spawning stream rates task");
+        tokio::spawn(StreamRateService::new(router.clone()).run());
+
+        // launch task to serve MAVLink commands requested via zenoh queries
+        info!("spawning command task");
+        tokio::spawn(
+            CommandService::new(self.zsession.clone(), router.clone(), self.access.clone()).run(),
+        );
+
+        // launch task to serve MAVLink parameters via zenoh queries and puts
+        info!("spawning parameters task");
+        tokio::spawn(
+            ParamService::new(self.zsession.clone(), router.clone(), self.access.clone()).run(),
+        );
+
+        // launch task to serve MAVLink missions via zenoh queries
+        info!("spawning mission task");
+        tokio::spawn(
+            MissionService::new(self.zsession.clone(), router.clone(), self.access.clone()).run(),
+        );
+
         // launch task to handle outgoing data for the zenoh network
-        if self.config.to_zenoh {
+        if config.to_zenoh {
             info!("spawning to_zenoh task");
-            let zsession = self.zsession.clone();
             tokio::spawn(
-                async move {
-                    let ke = keformat!(ke_liveliness_pub::formatter(), zenoh_id = "*",).unwrap();
-                    let publisher = zsession.declare_publisher(ke.clone()).await.unwrap();
-
-                    let mut rx = rx.resubscribe();
-                    loop {
-                        match rx.recv().await {
-                            Ok(msg) => {
-                                publisher.put(ZBytes::from(msg)).await.unwrap();
-                                debug!("forwarded message from broadcast channel to zenoh: {}", ke);
-                            }
-                            Err(e) => {
-                                error!("failed to read from broadcast channel: {e}");
-                            }
-                        }
-                    }
-                }
+                run_to_zenoh(
+                    self.zsession.clone(),
+                    config.clone(),
+                    self.statistics.clone(),
+                    rx.resubscribe(),
+                    stop,
+                )
                 .instrument(debug_span!("zenoh_pub_mav_out")),
             );
         }
 
         // launch task to handle incoming data for the zenoh network
-        if self.config.to_zenoh {
+        if config.from_zenoh {
             info!("spawning from_zenoh task");
-            let zsession = self.zsession.clone();
             tokio::spawn(
-                async move {
-                    let ke = keformat!(ke_liveliness_sub::formatter(), zenoh_id = "*",).unwrap();
-                    let subscriber = zsession.declare_subscriber(ke).await.unwrap();
-
-                    while let Ok(sample) = subscriber.recv_async().await {
-                        debug!("received message from zenoh");
-                        // TODO: serialize to mavlink raw?
-                        // TODO: tx.send(msg)
-                        debug!("forwarded message from zenoh to broadcast channel");
-                    }
-                }
+                run_from_zenoh(
+                    self.zsession.clone(),
+                    router.component.clone(),
+                    self.access.clone(),
+                    tx.clone(),
+                )
                 .instrument(debug_span!("zenoh_sub_mav_in")),
             );
         }
 
-        // only abort if all mavlink tasks were shutdown for some reason
-        while let Some(res) = set.join_next().await {
-            error!("task ended because: {res:?}");
+        loop {
+            select! {
+                Some(res) = set.join_next() => {
+                    match res {
+                        Err(e) if e.is_cancelled() => debug!("connection closed"),
+                        res => error!("task ended because: {res:?}"),
+                    }
+                    if set.is_empty() {
+                        error!("all connections aborted!");
+                    }
+                }
+                Ok(()) = config_updates.changed() => {
+                    let config = config_updates.borrow_and_update().clone();
+                    self.update_connections(&mut set, &mut connections, &config, &tx);
+                }
+                else => break,
+            }
         }
+    }
 
-        error!("all connections aborted!");
+    fn spawn_connection(
+        &self,
+        set: &mut JoinSet<std::io::Result<()>>,
+        mav_conn: MAVLinkConnection,
+        tx: &Sender<Protocol>,
+    ) -> RunningConnection {
+        info!("spawning task for {mav_conn:?}");
+        let (settings, settings_rx) = watch::channel(mav_conn.clone());
+        let serial_runtime = self
+            .serial_runtime
+            .as_ref()
+            .filter(|_| mav_conn.endpoint.starts_with("serial:"));
+        let handle = mav_conn.run(
+            tx.clone(),
+            self.statistics.clone(),
+            self.health.clone(),
+            settings_rx,
+        );
+        let task = match serial_runtime {
+            Some(serial_runtime) => set.spawn_on(handle, serial_runtime.handle()),
+            None => set.spawn(handle),
+        };
+        RunningConnection { settings, task }
     }
+
+    /// Opens, closes or updates the connections after a configuration reload.
+    fn update_connections(
+        &self,
+        set: &mut JoinSet<std::io::Result<()>>,
+        connections: &mut HashMap<String, RunningConnection>,
+        config: &Config,
+        tx: &Sender<Protocol>,
+    ) {
+        connections.retain(|endpoint, connection| {
+            let keep = config
+                .mavlink_connections
+                .iter()
+                .any(|c| &c.endpoint == endpoint);
+            if !keep {
+                info!("closing connection {endpoint}");
+                connection.task.abort();
+                self.health.remove(endpoint);
+            }
+            keep
+        });
+
+        for mav_conn in &config.mavlink_connections {
+            if let Some(connection) = connections.get(&mav_conn.endpoint) {
+                let current = connection.settings.borrow().clone();
+                if !connection.task.is_finished() && !current.needs_restart(mav_conn) {
+                    if current != *mav_conn {
+                        info!("updating connection {}", mav_conn.endpoint);
+                        connection.settings.send_replace(mav_conn.clone());
+                    }
+                    continue;
+                }
+                info!("reopening connection {}", mav_conn.endpoint);
+                connection.task.abort();
+            }
+            let connection = self.spawn_connection(set, mav_conn.clone(), tx);
+            connections.insert(mav_conn.endpoint.clone(), connection);
+        }
+    }
+}
+
+/// A MAVLink connection task, with its latest settings.
+struct RunningConnection {
+    settings: watch::Sender<MAVLinkConnection>,
+    task: AbortHandle,
+}
+
+/// Publishes the frames of the broadcast channel on zenoh, until the channel closes or `stop` is dropped.
+async fn run_to_zenoh(
+    zsession: Arc<Session>,
+    config: Arc<Config>,
+    statistics: Statistics,
+    mut rx: Receiver<Protocol>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let ke = keformat!(ke_liveliness_pub::formatter(), zenoh_id = "*",).unwrap();
+    let publishers = match Publishers::declare(&zsession, ke.clone(), &config.to_zenoh_qos).await {
+        Ok(publishers) => publishers,
+        Err(e) => {
+            error!("unable to declare publishers on {ke}: {e}");
+            return;
+        }
+    };
+    let history = History::new(&config.to_zenoh_history);
+    let history_task =
+        (!history.is_empty()).then(|| tokio::spawn(history.clone().run(zsession.clone())));
+    let mut batcher = config.to_zenoh_batch.as_ref().map(Batcher::new);
+    let compression = config.to_zenoh_compression.as_ref();
+
+    loop {
+        let deadline = batcher.as_ref().and_then(Batcher::deadline);
+        select! {
+            res = rx.recv() => match res {
+                Ok(msg) => {
+                    // do not echo messages coming from zenoh (or produced by the plugin)
+                    if msg.origin == ZENOH_ORIGIN {
+                        continue;
+                    }
+                    let index = publishers.index(msg.mav_frame.message_id());
+                    if config.to_zenoh_json {
+                        match to_json(&msg) {
+                            Ok(json) => {
+                                let encoding = Encoding::APPLICATION_JSON;
+                                history.keep(&msg, &json, &encoding);
+                                publish(publishers.get(index), json, encoding, compression).await;
+                                statistics.published(&msg.origin, msg.age());
+                            }
+                            Err(e) => debug!("unable to publish message as JSON: {e}"),
+                        }
+                        continue;
+                    }
+                    let frame = msg.raw_frame();
+                    history.keep(&msg, &frame, &frame_encoding(&frame));
+                    match batcher.as_mut() {
+                        Some(batcher) => {
+                            for batch in batcher.push(index, &frame) {
+                                let encoding = batch_encoding();
+                                publish(publishers.get(index), batch, encoding, compression).await;
+                            }
+                        }
+                        None => {
+                            let encoding = frame_encoding(&frame);
+                            publish(publishers.get(index), frame, encoding, compression).await;
+                        }
+                    }
+                    statistics.published(&msg.origin, msg.age());
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("to_zenoh lagged behind broadcast channel ({n} messages not published)");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                for (index, batch) in batcher.as_mut().unwrap().expired() {
+                    let encoding = batch_encoding();
+                    publish(publishers.get(index), batch, encoding, compression).await;
+                }
+            }
+            _ = &mut stop => break,
+        }
+    }
+
+    // do not lose the frames still held in batches
+    if let Some(batcher) = batcher.as_mut() {
+        for (index, batch) in batcher.flush() {
+            let encoding = batch_encoding();
+            publish(publishers.get(index), batch, encoding, compression).await;
+        }
+    }
+    if let Some(task) = history_task {
+        task.abort();
+    }
+}
+
+/// Decodes `msg` to a [`JsonMessage`].
+fn to_json(msg: &Protocol) -> Result<Vec<u8>, String> {
+    let message = Common::decode(msg.mav_frame.payload()).map_err(|e| e.to_string())?;
+    let json = JsonMessage {
+        system_id: Some(msg.mav_frame.system_id()),
+        component_id: Some(msg.mav_frame.component_id()),
+        message,
+    };
+    serde_json::to_vec(&json).map_err(|e| e.to_string())
+}
+
+/// Publishes `payload`, compressed if configured.
+async fn publish(
+    publisher: &Publisher<'_>,
+    payload: Vec<u8>,
+    encoding: Encoding,
+    compression: Option<&CompressionConfig>,
+) {
+    let compressed = compression.and_then(|c| c.compress(&payload).map(|p| (c.algorithm, p)));
+    let put = match compressed {
+        Some((algorithm, compressed)) => publisher.put(compressed).attachment(algorithm.name()),
+        None => publisher.put(payload),
+    };
+    if let Err(e) = put.encoding(encoding).await {
+        error!("failed to publish on {}: {e}", publisher.key_expr());
+    } else {
+        debug!(
+            "forwarded message from broadcast channel to zenoh: {}",
+            publisher.key_expr()
+        );
+    }
+}
+
+/// Injects the frames received from zenoh in the broadcast channel.
+async fn run_from_zenoh(
+    zsession: Arc<Session>,
+    component: Arc<Component>,
+    access: Arc<Access>,
+    tx: Sender<Protocol>,
+) {
+    let ke = keformat!(ke_liveliness_sub::formatter(), zenoh_id = "*",).unwrap();
+    // the keys below `in` are namespaces, that the access rules may restrict
+    let ke = format!("{ke}/**");
+    let subscriber = match zsession.declare_subscriber(ke.clone()).await {
+        Ok(subscriber) => subscriber,
+        Err(e) => {
+            error!("unable to declare subscriber on {ke}: {e}");
+            return;
+        }
+    };
+
+    while let Ok(sample) = subscriber.recv_async().await {
+        debug!("received message from zenoh");
+        let source = sample
+            .source_info()
+            .source_id
+            .map(|id| id.zid().to_string());
+        let Some(format) = Format::of(sample.encoding()) else {
+            error!(
+                "unsupported encoding received from zenoh: {}",
+                sample.encoding()
+            );
+            continue;
+        };
+        let mut bytes = sample.payload().to_bytes();
+        if let Some(attachment) = sample.attachment() {
+            let Some(algorithm) = CompressionAlgorithm::from_name(&attachment.to_bytes()) else {
+                error!("unsupported compression received from zenoh");
+                continue;
+            };
+            match decompress(algorithm, &bytes) {
+                Ok(decompressed) => bytes = decompressed.into(),
+                Err(e) => {
+                    error!("failed to decompress payload received from zenoh: {e}");
+                    continue;
+                }
+            }
+        }
+
+        let frames = match format {
+            Format::Batch => match split_batch(&bytes) {
+                Some(frames) => frames.into_iter().map(parse_raw_frame).collect(),
+                None => {
+                    error!("malformed batch received from zenoh");
+                    continue;
+                }
+            },
+            Format::Frames => {
+                let (frames, remaining) = split_raw_frames(&bytes);
+                if !remaining.is_empty() {
+                    error!(
+                        "ignoring {} trailing bytes received from zenoh",
+                        remaining.len()
+                    );
+                }
+                frames.into_iter().map(parse_raw_frame).collect()
+            }
+            Format::Json => match serde_json::from_slice::<JsonMessage>(&bytes) {
+                Ok(json) => vec![from_json(&component, &json)],
+                Err(e) => {
+                    error!("invalid JSON message received from zenoh: {e}");
+                    continue;
+                }
+            },
+        };
+
+        for frame in frames {
+            match frame {
+                Ok(mav_frame) => {
+                    let msg = Protocol::new(ZENOH_ORIGIN, mav_frame);
+                    if !access.authorize(&msg, sample.key_expr(), source.as_deref()) {
+                        continue;
+                    }
+                    if let Err(e) = tx.send(msg) {
+                        error!("could not send broadcast message: {e}");
+                    } else {
+                        debug!("forwarded message from zenoh to broadcast channel");
+                    }
+                }
+                Err(e) => error!("invalid MAVLink frame received from zenoh: {e}"),
+            }
+        }
+    }
+}
+
+/// Encodes a [`JsonMessage`], with the plugin's identity unless specified.
+fn from_json(component: &Component, json: &JsonMessage) -> mavio::errors::Result<MavFrame> {
+    let system_id = json.system_id.unwrap_or(component.system_id);
+    let component_id = json.component_id.unwrap_or(component.component_id);
+    // frames of each identity are numbered on their own
+    let sequence = format!("{ZENOH_ORIGIN}:{system_id}/{component_id}");
+    component.frame_as(&sequence, system_id, component_id, &json.message)
 }
 
 impl From<Protocol> for ZBytes {
     fn from(value: Protocol) -> Self {
-        let mav_frame = value.mav_frame;
-        let raw_frame = mav_frame.into_versionless();
-        let header = raw_frame.header();
-        let header_bytes = header.decode();
-        let payload_bytes = raw_frame.payload().bytes();
-        let checksum = raw_frame.checksum();
-
-        let mut zbytes = Vec::new();
-        zbytes.extend_from_slice(header_bytes.as_slice());
-        zbytes.extend_from_slice(payload_bytes);
-        zbytes.extend_from_slice(&checksum.to_le_bytes());
-        // TODO? signature?
-
-        ZBytes::from(zbytes)
+        ZBytes::from(value.raw_frame())
     }
 }
@@ -6,4 +6,9 @@ kedefine!(
     pub ke_liveliness_plugin: "@/${zenoh_id:*}/@mavlink",
     pub(crate) ke_liveliness_sub: "@/${zenoh_id:*}/@mavlink/v2/in",
     pub(crate) ke_liveliness_pub: "@/${zenoh_id:*}/@mavlink/v2/out",
-);
\ No newline at end of file
+    pub(crate) ke_liveliness_stats: "@/${zenoh_id:*}/@mavlink/v2/stats",
+    pub ke_liveliness_health: "@/${zenoh_id:*}/@mavlink/v2/health",
+    pub(crate) ke_liveliness_cmd: "@/${zenoh_id:*}/@mavlink/v2/cmd/${system_id:*}/${component_id:*}",
+    pub(crate) ke_liveliness_params: "@/${zenoh_id:*}/@mavlink/v2/params/${system_id:*}/${component_id:*}/${name:*}",
+    pub(crate) ke_liveliness_mission: "@/${zenoh_id:*}/@mavlink/v2/mission/${system_id:*}/${component_id:*}/${action:*}",
+);
@@ -1,37 +1,326 @@
-use mavio::{io::connect_async, prelude::Versionless};
-use serde::{
-    Deserialize, Serialize,
+use std::{
+    net::{TcpListener, ToSocketAddrs, UdpSocket},
+    path::Path,
+    str::FromStr,
 };
+
+use mavio::{io::connect_async, prelude::Versionless, protocol::MavLinkVersion, MavFrame};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::{
     select,
-    sync::broadcast::{Receiver, Sender},
+    sync::{
+        broadcast::{Receiver, Sender},
+        watch,
+    },
 };
-use tracing::{debug, error, info, instrument, trace};
+use tracing::{debug, error, info, instrument, trace, warn};
 
-use crate::protocol::Protocol;
+use crate::{
+    config::duration,
+    filter::{is_version, MessageFilter},
+    health::Health,
+    protocol::Protocol,
+    replay::{replay, ReplayOptions, REPLAY_PREFIX},
+    sim::{rate_period, simulate, SimOptions, SIM_PREFIX},
+    stats::Statistics,
+    stream_rates::StreamRates,
+};
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct MAVLinkConnection {
-    /// MAVLink endpoint, following [`mavlink::connect_async`] protocols.
+    /// MAVLink endpoint, following [`mavlink::connect_async`] protocols, `replay:<path>` to replay a `.tlog` file
+    /// or `sim:<system_id>` to simulate a vehicle.
     #[serde(default)]
     pub endpoint: String,
-    // TODO: mav version ??? even tho Versionless does the job
+    /// Only exchange frames of this MAVLink version (1 or 2) on this connection, frames of the other version are dropped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mavlink_version: Option<u8>,
+    /// Messages exchanged (read and written) on this connection.
+    #[serde(default, skip_serializing_if = "MessageFilter::is_empty")]
+    pub filter: MessageFilter,
+    /// Periodically send the plugin's `HEARTBEAT` through this connection.
+    #[serde(default)]
+    pub heartbeat: bool,
+    /// The connection is unhealthy when no frame was received for this time (in seconds), 0 to disable (e.g. for
+    /// output-only connections). Defaults to the plugin's `health.timeout`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_timeout: Option<f32>,
+    /// Telemetry rates requested from every vehicle heard on this connection.
+    #[serde(default)]
+    pub stream_rates: StreamRates,
+    /// Replay options of `replay:` endpoints.
+    #[serde(default)]
+    pub replay: ReplayOptions,
+    /// Simulated vehicle options of `sim:` endpoints.
+    #[serde(default)]
+    pub sim: SimOptions,
+}
+
+/// Parses `<endpoint>[,<option>=<value>...]`, e.g. `udpout:127.0.0.1:14550,version=1,filter=0|24|!76`.
+///
+/// Options are fields of [`MAVLinkConnection`] (nested ones with dots, e.g. `replay.speed=2`), valued in JSON
+/// or as plain strings. `version` stands for `mavlink_version`, and `filter` takes message ids separated by `|`,
+/// the ones prefixed by `!` being denied and the others allowed.
+impl FromStr for MAVLinkConnection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let endpoint = parts.next().unwrap_or_default();
+        if endpoint.is_empty() {
+            return Err(format!("missing endpoint in `{s}`"));
+        }
+
+        let mut connection = json!({ "endpoint": endpoint });
+        for option in parts {
+            let (key, value) = option.split_once('=').ok_or_else(|| {
+                format!("invalid option `{option}` (expecting `<option>=<value>`)")
+            })?;
+            let (key, value) = match key {
+                "version" => ("mavlink_version", parse_option_value(value)),
+                "filter" => ("filter", parse_filter(value)?),
+                _ => (key, parse_option_value(value)),
+            };
+            insert_option(&mut connection, key, value)?;
+        }
+        serde_json::from_value(connection).map_err(|e| format!("invalid connection `{s}`: {e}"))
+    }
+}
+
+fn parse_option_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+fn parse_filter(value: &str) -> Result<Value, String> {
+    let mut filter = MessageFilter::default();
+    for id in value.split('|').filter(|id| !id.is_empty()) {
+        let (ids, id) = match id.strip_prefix('!') {
+            Some(id) => (&mut filter.deny, id),
+            None => (&mut filter.allow, id),
+        };
+        ids.insert(
+            id.parse()
+                .map_err(|e| format!("invalid message id `{id}` in filter: {e}"))?,
+        );
+    }
+    Ok(serde_json::to_value(filter).unwrap())
+}
+
+/// Inserts `value` at the dotted `key` of `object`.
+fn insert_option(object: &mut Value, key: &str, value: Value) -> Result<(), String> {
+    let mut target = object;
+    let mut fields = key.split('.').peekable();
+    while let Some(field) = fields.next() {
+        let Value::Object(map) = target else {
+            return Err(format!(
+                "invalid option `{key}`: `{field}` is not in an object"
+            ));
+        };
+        if fields.peek().is_none() {
+            map.insert(field.to_string(), value);
+            return Ok(());
+        }
+        target = map
+            .entry(field)
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+    Err(format!("invalid option `{key}`"))
 }
 
 impl MAVLinkConnection {
+    /// Checks the endpoint syntax and the connection options. Returns the problems found.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(e) = self.protocol_address() {
+            problems.push(e);
+        }
+        if let Some(version) = self.mavlink_version {
+            if version != 1 && version != 2 {
+                problems.push(format!(
+                    "invalid mavlink_version {version} (expecting 1 or 2)"
+                ));
+            }
+        }
+        if let Some(timeout) = self.health_timeout.filter(|t| duration(*t).is_none()) {
+            problems.push(format!(
+                "invalid health_timeout {timeout} (expecting a duration in seconds)"
+            ));
+        }
+        for id in self.filter.allow.intersection(&self.filter.deny) {
+            problems.push(format!(
+                "message {id} is both allowed and denied by the filter"
+            ));
+        }
+        if !(self.replay.speed >= 0.0 && self.replay.speed.is_finite()) {
+            problems.push(format!(
+                "replay: invalid speed {} (expecting a factor, or 0 for as fast as possible)",
+                self.replay.speed
+            ));
+        }
+        for (id, rate) in &self.sim.rates {
+            if *rate != 0.0 {
+                if let Err(e) = rate_period(*rate) {
+                    problems.push(format!("sim: message {id}: {e}"));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Checks that the endpoint is usable right now (serial device or file present, listening port free, address
+    /// resolvable). Malformed endpoints are reported by [`check`](Self::check).
+    pub fn check_environment(&self) -> Result<(), String> {
+        let Ok((protocol, address)) = self.protocol_address() else {
+            return Ok(());
+        };
+        match protocol {
+            "tcpin" => TcpListener::bind(address)
+                .map(drop)
+                .map_err(|e| format!("unable to listen on {address}: {e}")),
+            "udpin" => UdpSocket::bind(address)
+                .map(drop)
+                .map_err(|e| format!("unable to listen on {address}: {e}")),
+            "tcpout" | "udpout" | "udpbcast" => address
+                .to_socket_addrs()
+                .map(drop)
+                .map_err(|e| format!("invalid address {address}: {e}")),
+            "serial" => {
+                let path = address.rsplit_once(':').map_or(address, |(path, _)| path);
+                if path.starts_with('/') && !Path::new(path).exists() {
+                    return Err(format!("serial device {path} not found"));
+                }
+                Ok(())
+            }
+            "file" | "replay" => {
+                if !Path::new(address).is_file() {
+                    return Err(format!("file {address} not found"));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Splits the endpoint into its protocol and address, checking their syntax.
+    fn protocol_address(&self) -> Result<(&str, &str), String> {
+        let (protocol, address) = self
+            .endpoint
+            .split_once(':')
+            .ok_or_else(|| "invalid endpoint (expecting <protocol>:<address>)".to_string())?;
+        match protocol {
+            "tcpin" | "udpin" | "tcpout" | "udpout" | "udpbcast" | "file" | "replay" => {}
+            "serial" => {
+                let (_, baudrate) = address.rsplit_once(':').ok_or_else(|| {
+                    "invalid serial endpoint (expecting serial:<path>:<baudrate>)".to_string()
+                })?;
+                baudrate
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid baud rate {baudrate}: {e}"))?;
+            }
+            "sim" => {
+                address.parse::<u8>().map_err(|_| {
+                    "invalid simulated vehicle endpoint (expecting sim:<system_id>)".to_string()
+                })?;
+            }
+            _ => return Err(format!("unknown protocol {protocol}")),
+        }
+        Ok((protocol, address))
+    }
+
+    /// Returns whether applying `other` settings requires reopening the connection. Filters, versions,
+    /// heartbeats and stream rates are applied to the open connection.
+    pub fn needs_restart(&self, other: &MAVLinkConnection) -> bool {
+        self.endpoint != other.endpoint || self.replay != other.replay || self.sim != other.sim
+    }
+
+    /// MAVLink version of the frames the plugin originates for this connection: MAVLink 2, unless restricted to
+    /// MAVLink 1.
+    pub fn frame_version(&self) -> MavLinkVersion {
+        match self.mavlink_version {
+            Some(1) => MavLinkVersion::V1,
+            _ => MavLinkVersion::V2,
+        }
+    }
+
+    /// Returns whether `frame` may be read from or written to this connection.
+    pub fn accepts(&self, frame: &MavFrame) -> bool {
+        self.mavlink_version
+            .map_or(true, |version| is_version(frame, version))
+            && self.filter.allows(frame.message_id())
+    }
+
+    /// Handle a MAVLink connection, reopening it (with its latest `settings`) when it fails or is closed by the
+    /// remote side. Replays and simulated vehicles are not reopened.
+    pub async fn run(
+        self,
+        tx: Sender<Protocol>,
+        stats: Statistics,
+        health: Health,
+        settings: watch::Receiver<MAVLinkConnection>,
+    ) -> std::io::Result<()> {
+        if self.endpoint.starts_with(REPLAY_PREFIX) || self.endpoint.starts_with(SIM_PREFIX) {
+            let rx = tx.subscribe();
+            return self.handle((tx, rx), stats, health, settings).await;
+        }
+
+        let delay = health.reconnect_delay();
+        loop {
+            let connection = settings.borrow().clone();
+            health.connecting(&connection.endpoint, connection.health_timeout);
+            let rx = tx.subscribe();
+            let res = connection
+                .handle(
+                    (tx.clone(), rx),
+                    stats.clone(),
+                    health.clone(),
+                    settings.clone(),
+                )
+                .await;
+            health.disconnected(&self.endpoint);
+            match res {
+                Ok(()) => warn!(
+                    "connection {} closed, reopening it in {delay:?}",
+                    self.endpoint
+                ),
+                Err(e) => error!(
+                    "connection {} failed: {e}, reopening it in {delay:?}",
+                    self.endpoint
+                ),
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
     /// Handle a MAVLink connection.
     ///
     /// This means:
     /// - Read from the connection and broadcast outgoing MAVLink data.
     /// - Fetch broadcast channel and write incoming MAVLink data to the connection.
-    #[instrument(skip(broadcast_channel))]
+    /// - Count the frames read in `stats`, and record the connection's liveliness in `health`.
+    ///
+    /// Frames are filtered with the latest `settings`, updated when the configuration is reloaded.
+    #[instrument(skip(broadcast_channel, stats, health, settings))]
     pub async fn handle(
         self,
         mut broadcast_channel: (Sender<Protocol>, Receiver<Protocol>),
+        stats: Statistics,
+        health: Health,
+        settings: watch::Receiver<MAVLinkConnection>,
     ) -> std::io::Result<()> {
+        if let Some(path) = self.endpoint.strip_prefix(REPLAY_PREFIX) {
+            let path = Path::new(path);
+            return replay(&self.endpoint, path, &self.replay, &broadcast_channel.0).await;
+        }
+        if self.endpoint.starts_with(SIM_PREFIX) {
+            return simulate(&self.endpoint, &self.sim, broadcast_channel).await;
+        }
+
         info!("connecting");
         let mut connection = connect_async::<Versionless>(&self.endpoint).await?;
         info!("connected");
+        health.connected(&self.endpoint);
 
         loop {
             select! {
@@ -42,17 +331,27 @@ impl MAVLinkConnection {
                             debug!("received mav frame from connection (id = {})", frame.message_id());
                             trace!(?frame);
                             let broadcast_msg = Protocol::new(&self.endpoint, frame.into_mav_frame());
+                            stats.record(&self.endpoint, &broadcast_msg.mav_frame, broadcast_msg.raw_frame_len());
+                            health.received(&self.endpoint);
+                            if !settings.borrow().accepts(&broadcast_msg.mav_frame) {
+                                trace!("ignoring frame filtered out by connection settings");
+                                continue;
+                            }
                             if let Err(e) = broadcast_channel.0.send(broadcast_msg) {
                                 error!("could not send broadcast message: {e}");
                             } else {
                                 debug!("forwarded raw mavlink message from connection to broadcast channel");
                             }
                         }
-                        Err(e) => {
+                        Err(mavio::errors::Error::Io(e)) => {
                             error!("failed to read from mavlink connection: {e}");
                             break;
                         }
-                        // TODO: handle certain errors accordingly to decide if exit or try again (?)
+                        Err(e) => {
+                            // CRC or parse error, the connection itself is fine
+                            warn!("invalid frame read from mavlink connection: {e}");
+                            stats.error(&self.endpoint);
+                        }
                     }
                 }
                 // Fetch broadcast channel and write incoming MAVLink data to the connection.
@@ -60,9 +359,13 @@ impl MAVLinkConnection {
                     match res {
                         Ok(msg) => {
                             trace!("received message from broadcast channel");
-                            // we only consume and write if its not the message we emitted
-                            if msg.origin == self.endpoint { // FIXME: is this slow
-                                trace!("ignoring messsage because it was produced by the same origin");
+                            // we only consume and write if its not the message we emitted (or targeted to another connection)
+                            if !msg.is_for(&self.endpoint) { // FIXME: is this slow
+                                trace!("ignoring messsage because it was produced by the same origin or targets another connection");
+                                continue;
+                            }
+                            if !settings.borrow().accepts(&msg.mav_frame) {
+                                trace!("ignoring message filtered out by connection settings");
                                 continue;
                             }
 
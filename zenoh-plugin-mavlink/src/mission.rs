@@ -0,0 +1,598 @@
+//! MAVLink mission protocol exposed as a Zenoh queryable on
+//! `@/<zenoh_id>/@mavlink/v2/mission/<system_id>/<component_id>/<action>`, where `<action>` is:
+//!
+//! - `download`: downloads the current mission and replies it as a QGroundControl `.plan` JSON,
+//! - `upload`: uploads the `.plan` JSON carried by the query payload,
+//! - `clear`: clears the current mission.
+//!
+//! Only `SimpleItem` mission items are supported. For ArduPilot plans (`firmwareType` 3), the planned
+//! home position is uploaded as item 0 and removed from downloaded missions, as QGroundControl does.
+//!
+//! Uploads and clears are authorized by the `from_zenoh_access` rule of the vehicle (see [`crate::access`]): an
+//! uploaded mission may not run a denied command the rule does not permit.
+//!
+//! Optional selector parameters: `timeout` (milliseconds) and `retries`.
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use mavio::dialects::common::{
+    enums::{MavCmd, MavFrame as CoordinateFrame},
+    messages::{
+        MissionAck, MissionClearAll, MissionCount, MissionItemInt, MissionRequest,
+        MissionRequestInt, MissionRequestList,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+use zenoh::{key_expr::format::keformat, query::Query, Session};
+
+use crate::{
+    access::{Access, MISSION_CLEAR_ALL},
+    liveliness::ke_liveliness_mission,
+    protocol::Protocol,
+    routing::Router,
+    service::{parameter, parse_target, recv_from, timeout_parameter},
+};
+
+/// Time to wait for the next mission message before retransmitting.
+pub const DEFAULT_MISSION_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Number of retransmissions of a single step before giving up.
+pub const DEFAULT_MISSION_RETRIES: u8 = 5;
+
+/// `MAV_MISSION_ACCEPTED`
+const MAV_MISSION_ACCEPTED: u8 = 0;
+/// `MAV_MISSION_TYPE_MISSION`
+const MAV_MISSION_TYPE_MISSION: u8 = 0;
+/// `MAV_AUTOPILOT_ARDUPILOTMEGA`
+const MAV_AUTOPILOT_ARDUPILOTMEGA: u8 = 3;
+/// `MAV_CMD_NAV_WAYPOINT`
+const MAV_CMD_NAV_WAYPOINT: u16 = 16;
+/// `MAV_FRAME_GLOBAL`
+const MAV_FRAME_GLOBAL: u8 = 0;
+/// `MAV_FRAME_MISSION`
+const MAV_FRAME_MISSION: u8 = 2;
+
+/// QGroundControl plan file (only the mission part is used).
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Plan {
+    #[serde(default = "plan_file_type")]
+    pub file_type: String,
+    #[serde(default = "plan_version")]
+    pub version: u8,
+    #[serde(default = "plan_ground_station")]
+    pub ground_station: String,
+    pub mission: PlanMission,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanMission {
+    #[serde(default = "plan_mission_version")]
+    pub version: u8,
+    #[serde(default)]
+    pub firmware_type: u8,
+    #[serde(default)]
+    pub vehicle_type: u8,
+    #[serde(default)]
+    pub planned_home_position: Option<[f64; 3]>,
+    #[serde(default)]
+    pub items: Vec<PlanItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanItem {
+    #[serde(rename = "type")]
+    pub item_type: String,
+    #[serde(default = "default_true")]
+    pub auto_continue: bool,
+    pub command: u16,
+    #[serde(default)]
+    pub do_jump_id: Option<u16>,
+    pub frame: u8,
+    /// 7 parameters, `null` standing for NaN. Latitude and longitude are in degrees.
+    pub params: [Option<f64>; 7],
+}
+
+fn plan_file_type() -> String {
+    "Plan".to_string()
+}
+
+fn plan_version() -> u8 {
+    1
+}
+
+fn plan_ground_station() -> String {
+    "zenoh-plugin-mavlink".to_string()
+}
+
+fn plan_mission_version() -> u8 {
+    2
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `x`/`y` of global frames are latitude/longitude in degrees * 1e7, local frames are meters * 1e4.
+fn position_scale(frame: u8) -> Option<f64> {
+    match frame {
+        // MAV_FRAME_GLOBAL, MAV_FRAME_GLOBAL_RELATIVE_ALT, MAV_FRAME_GLOBAL_INT,
+        // MAV_FRAME_GLOBAL_RELATIVE_ALT_INT, MAV_FRAME_GLOBAL_TERRAIN_ALT, MAV_FRAME_GLOBAL_TERRAIN_ALT_INT
+        0 | 3 | 5 | 6 | 10 | 11 => Some(1e7),
+        MAV_FRAME_MISSION => None,
+        _ => Some(1e4),
+    }
+}
+
+impl PlanItem {
+    fn to_mission_item(
+        &self,
+        system_id: u8,
+        component_id: u8,
+        seq: u16,
+    ) -> Result<MissionItemInt, String> {
+        if self.item_type != "SimpleItem" {
+            return Err(format!("unsupported mission item type {}", self.item_type));
+        }
+        let param = |i: usize| self.params[i].unwrap_or(f64::NAN);
+        let position = |i: usize| match position_scale(self.frame) {
+            Some(scale) => (param(i) * scale).round() as i32,
+            None => param(i) as i32,
+        };
+
+        Ok(MissionItemInt {
+            target_system: system_id,
+            target_component: component_id,
+            seq,
+            frame: CoordinateFrame::try_from(self.frame)
+                .map_err(|_| format!("invalid frame {}", self.frame))?,
+            command: MavCmd::try_from(self.command)
+                .map_err(|_| format!("unknown command {}", self.command))?,
+            current: 0,
+            autocontinue: self.auto_continue as u8,
+            param1: param(0) as f32,
+            param2: param(1) as f32,
+            param3: param(2) as f32,
+            param4: param(3) as f32,
+            x: position(4),
+            y: position(5),
+            z: param(6) as f32,
+            ..Default::default()
+        })
+    }
+
+    fn from_mission_item(item: &MissionItemInt) -> Self {
+        let frame = item.frame as u8;
+        let param = |v: f32| (!v.is_nan()).then_some(v as f64);
+        let position = |v: i32| match position_scale(frame) {
+            Some(scale) => v as f64 / scale,
+            None => v as f64,
+        };
+
+        Self {
+            item_type: "SimpleItem".to_string(),
+            auto_continue: item.autocontinue != 0,
+            command: item.command as u16,
+            do_jump_id: Some(item.seq + 1),
+            frame,
+            params: [
+                param(item.param1),
+                param(item.param2),
+                param(item.param3),
+                param(item.param4),
+                Some(position(item.x)),
+                Some(position(item.y)),
+                param(item.z),
+            ],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct MissionService {
+    zsession: Arc<Session>,
+    router: Router,
+    access: Arc<Access>,
+}
+
+impl MissionService {
+    pub fn new(zsession: Arc<Session>, router: Router, access: Arc<Access>) -> Self {
+        Self {
+            zsession,
+            router,
+            access,
+        }
+    }
+
+    pub async fn run(self) {
+        let ke = keformat!(
+            ke_liveliness_mission::formatter(),
+            zenoh_id = self.zsession.zid().into_keyexpr(),
+            system_id = "*",
+            component_id = "*",
+            action = "*",
+        )
+        .unwrap();
+        let queryable = match self.zsession.declare_queryable(ke.clone()).await {
+            Ok(queryable) => queryable,
+            Err(e) => {
+                error!("unable to declare mission queryable on {ke}: {e}");
+                return;
+            }
+        };
+
+        info!("serving MAVLink missions on {ke}");
+        while let Ok(query) = queryable.recv_async().await {
+            let service = self.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = service.handle(&query).await {
+                        warn!("mission query {} failed: {e}", query.selector());
+                        if let Err(e) = query.reply_err(e).await {
+                            error!("failed to reply to mission query: {e}");
+                        }
+                    }
+                }
+                .instrument(debug_span!("mav_mission")),
+            );
+        }
+    }
+
+    async fn handle(&self, query: &Query) -> Result<(), String> {
+        let ke = ke_liveliness_mission::parse(query.key_expr()).map_err(|e| e.to_string())?;
+        let (system_id, component_id) = parse_target(ke.system_id(), ke.component_id())?;
+        let step = Step {
+            router: &self.router,
+            system_id,
+            component_id,
+            timeout: timeout_parameter(query, "timeout", DEFAULT_MISSION_TIMEOUT)?,
+            retries: parameter(query, "retries", DEFAULT_MISSION_RETRIES)?,
+        };
+
+        let reply = match ke.action().as_str() {
+            "download" => {
+                let items = step.download().await?;
+                let mut mission = PlanMission {
+                    version: plan_mission_version(),
+                    firmware_type: 0,
+                    vehicle_type: 0,
+                    planned_home_position: None,
+                    items: items.iter().map(PlanItem::from_mission_item).collect(),
+                };
+                // ArduPilot stores the home position as item 0
+                if let Some(home) = items.first().filter(|_| self.is_ardupilot(system_id)) {
+                    mission.firmware_type = MAV_AUTOPILOT_ARDUPILOTMEGA;
+                    mission.planned_home_position =
+                        Some([home.x as f64 / 1e7, home.y as f64 / 1e7, home.z as f64]);
+                    mission.items.remove(0);
+                }
+                serde_json::to_vec(&Plan {
+                    file_type: plan_file_type(),
+                    version: plan_version(),
+                    ground_station: plan_ground_station(),
+                    mission,
+                })
+                .unwrap()
+            }
+            "upload" => {
+                let payload = query
+                    .payload()
+                    .ok_or_else(|| "missing plan in query payload".to_string())?;
+                let plan: Plan =
+                    serde_json::from_slice(&payload.to_bytes()).map_err(|e| e.to_string())?;
+
+                let mut items = Vec::new();
+                if plan.mission.firmware_type == MAV_AUTOPILOT_ARDUPILOTMEGA {
+                    let home = plan.mission.planned_home_position.unwrap_or_default();
+                    items.push(
+                        PlanItem {
+                            item_type: "SimpleItem".to_string(),
+                            auto_continue: true,
+                            command: MAV_CMD_NAV_WAYPOINT,
+                            do_jump_id: None,
+                            frame: MAV_FRAME_GLOBAL,
+                            params: [
+                                Some(0.0),
+                                Some(0.0),
+                                Some(0.0),
+                                Some(0.0),
+                                Some(home[0]),
+                                Some(home[1]),
+                                Some(home[2]),
+                            ],
+                        }
+                        .to_mission_item(system_id, component_id, 0)?,
+                    );
+                }
+                for item in &plan.mission.items {
+                    let seq = item_count(items.len())?;
+                    items.push(item.to_mission_item(system_id, component_id, seq)?);
+                }
+                let commands: BTreeSet<u16> = items.iter().map(|i| i.command as u16).collect();
+                self.access
+                    .authorize_mission(system_id, &commands, query.key_expr(), None)?;
+                step.upload(&items).await?;
+                format!("{{\"count\": {}}}", items.len()).into_bytes()
+            }
+            "clear" => {
+                self.access.authorize_message(
+                    system_id,
+                    MISSION_CLEAR_ALL,
+                    query.key_expr(),
+                    None,
+                )?;
+                step.clear().await?;
+                b"{}".to_vec()
+            }
+            action => return Err(format!("unknown mission action {action}")),
+        };
+
+        query
+            .reply(query.key_expr().clone(), reply)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// ArduPilot is recognized by the autopilot field of its heartbeat.
+    fn is_ardupilot(&self, system_id: u8) -> bool {
+        self.router.autopilot(system_id) == Some(MAV_AUTOPILOT_ARDUPILOTMEGA)
+    }
+}
+
+/// Number (or sequence number) of mission items, which MAVLink carries as `u16`.
+fn item_count(len: usize) -> Result<u16, String> {
+    u16::try_from(len).map_err(|_| format!("too many mission items (more than {})", u16::MAX))
+}
+
+/// Drives one mission transaction with a vehicle.
+struct Step<'a> {
+    router: &'a Router,
+    system_id: u8,
+    component_id: u8,
+    timeout: Duration,
+    retries: u8,
+}
+
+impl Step<'_> {
+    async fn download(&self) -> Result<Vec<MissionItemInt>, String> {
+        let mut rx = self.router.subscribe();
+        let request_list = MissionRequestList {
+            target_system: self.system_id,
+            target_component: self.component_id,
+            ..Default::default()
+        };
+        let count = self
+            .exchange(&mut rx, &request_list, |count: &MissionCount| {
+                self.is_for_us(
+                    count.target_system,
+                    count.target_component,
+                    count.mission_type as u8,
+                )
+            })
+            .await?
+            .count;
+        debug!(
+            "downloading {count} mission items from {}/{}",
+            self.system_id, self.component_id
+        );
+
+        let mut items = Vec::with_capacity(count as usize);
+        for seq in 0..count {
+            let request = MissionRequestInt {
+                target_system: self.system_id,
+                target_component: self.component_id,
+                seq,
+                ..Default::default()
+            };
+            items.push(
+                self.exchange(&mut rx, &request, |item: &MissionItemInt| {
+                    item.seq == seq
+                        && self.is_for_us(
+                            item.target_system,
+                            item.target_component,
+                            item.mission_type as u8,
+                        )
+                })
+                .await?,
+            );
+        }
+
+        self.router.send(
+            self.system_id,
+            self.component_id,
+            &MissionAck {
+                target_system: self.system_id,
+                target_component: self.component_id,
+                ..Default::default()
+            },
+        )?;
+        Ok(items)
+    }
+
+    async fn upload(&self, items: &[MissionItemInt]) -> Result<(), String> {
+        let mut rx = self.router.subscribe();
+        let count = MissionCount {
+            target_system: self.system_id,
+            target_component: self.component_id,
+            count: item_count(items.len())?,
+            ..Default::default()
+        };
+        debug!(
+            "uploading {} mission items to {}/{}",
+            items.len(),
+            self.system_id,
+            self.component_id
+        );
+
+        // the vehicle requests every item, in any order and possibly several times, then acks
+        let mut requested: Option<u16> = None;
+        let mut attempts = 0;
+        loop {
+            match requested {
+                None => self
+                    .router
+                    .send(self.system_id, self.component_id, &count)?,
+                Some(seq) => {
+                    self.router
+                        .send(self.system_id, self.component_id, &items[seq as usize])?
+                }
+            }
+
+            let deadline = Instant::now() + self.timeout;
+            match recv_from(
+                &mut rx,
+                self.system_id,
+                self.component_id,
+                deadline,
+                |reply: &UploadReply| {
+                    self.is_for_us(
+                        reply.target_system,
+                        reply.target_component,
+                        reply.mission_type,
+                    )
+                },
+            )
+            .await?
+            .map(|reply| reply.step)
+            {
+                Some(UploadStep::Request(seq)) if seq as usize >= items.len() => {
+                    return Err(format!("vehicle requested unknown mission item {seq}"))
+                }
+                Some(UploadStep::Request(seq)) => {
+                    requested = Some(seq);
+                    attempts = 0;
+                }
+                Some(UploadStep::Ack(MAV_MISSION_ACCEPTED)) => return Ok(()),
+                Some(UploadStep::Ack(result)) => {
+                    return Err(format!(
+                        "vehicle rejected mission (MAV_MISSION_RESULT {result})"
+                    ))
+                }
+                None if attempts == self.retries => {
+                    return Err(format!(
+                        "no mission request from {}/{}",
+                        self.system_id, self.component_id
+                    ))
+                }
+                None => attempts += 1,
+            }
+        }
+    }
+
+    async fn clear(&self) -> Result<(), String> {
+        let mut rx = self.router.subscribe();
+        let clear = MissionClearAll {
+            target_system: self.system_id,
+            target_component: self.component_id,
+            ..Default::default()
+        };
+        let ack = self
+            .exchange(&mut rx, &clear, |ack: &MissionAck| {
+                self.is_for_us(
+                    ack.target_system,
+                    ack.target_component,
+                    ack.mission_type as u8,
+                )
+            })
+            .await?;
+        match ack.type_ as u8 {
+            MAV_MISSION_ACCEPTED => Ok(()),
+            result => Err(format!(
+                "vehicle rejected mission clear (MAV_MISSION_RESULT {result})"
+            )),
+        }
+    }
+
+    /// Replies are addressed to the plugin (target 0 is accepted for older autopilots), and about the mission
+    /// (not the geofence or the rally points).
+    fn is_for_us(&self, target_system: u8, target_component: u8, mission_type: u8) -> bool {
+        let component = &self.router.component;
+        (target_system == 0 || target_system == component.system_id)
+            && (target_component == 0 || target_component == component.component_id)
+            && mission_type == MAV_MISSION_TYPE_MISSION
+    }
+
+    /// Sends `message` until the vehicle answers with a matching `M`.
+    async fn exchange<R, M, F>(
+        &self,
+        rx: &mut tokio::sync::broadcast::Receiver<Protocol>,
+        message: &R,
+        filter: F,
+    ) -> Result<M, String>
+    where
+        R: mavio::Message,
+        M: for<'a> TryFrom<&'a mavio::protocol::Payload>,
+        F: Fn(&M) -> bool,
+    {
+        for _ in 0..=self.retries {
+            self.router
+                .send(self.system_id, self.component_id, message)?;
+            let deadline = Instant::now() + self.timeout;
+            if let Some(answer) =
+                recv_from(rx, self.system_id, self.component_id, deadline, &filter).await?
+            {
+                return Ok(answer);
+            }
+        }
+        Err(format!(
+            "no answer from {}/{} after {} attempts",
+            self.system_id,
+            self.component_id,
+            self.retries as u16 + 1
+        ))
+    }
+}
+
+/// Messages a vehicle sends during a mission upload.
+enum UploadStep {
+    Request(u16),
+    Ack(u8),
+}
+
+/// An [`UploadStep`], with the target and mission type of its message.
+struct UploadReply {
+    step: UploadStep,
+    target_system: u8,
+    target_component: u8,
+    mission_type: u8,
+}
+
+impl TryFrom<&mavio::protocol::Payload> for UploadReply {
+    type Error = ();
+
+    fn try_from(payload: &mavio::protocol::Payload) -> Result<Self, Self::Error> {
+        let reply = |step, target_system, target_component, mission_type| Self {
+            step,
+            target_system,
+            target_component,
+            mission_type,
+        };
+        if let Ok(m) = MissionRequestInt::try_from(payload) {
+            Ok(reply(
+                UploadStep::Request(m.seq),
+                m.target_system,
+                m.target_component,
+                m.mission_type as u8,
+            ))
+        } else if let Ok(m) = MissionRequest::try_from(payload) {
+            Ok(reply(
+                UploadStep::Request(m.seq),
+                m.target_system,
+                m.target_component,
+                m.mission_type as u8,
+            ))
+        } else if let Ok(m) = MissionAck::try_from(payload) {
+            Ok(reply(
+                UploadStep::Ack(m.type_ as u8),
+                m.target_system,
+                m.target_component,
+                m.mission_type as u8,
+            ))
+        } else {
+            Err(())
+        }
+    }
+}
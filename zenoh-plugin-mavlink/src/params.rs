@@ -0,0 +1,565 @@
+//! MAVLink parameter protocol exposed on `@/<zenoh_id>/@mavlink/v2/params/<system_id>/<component_id>/<name>`.
+//!
+//! - A query on `<name>` reads the parameter (`PARAM_REQUEST_READ`) and replies a JSON [`Param`].
+//! - A query on `*` replies every parameter. The full list is downloaded (`PARAM_REQUEST_LIST`, then
+//!   `PARAM_REQUEST_READ` for each missing index) if it is not cached yet or if `refresh` is set.
+//! - A put on `<name>` sets the parameter (`PARAM_SET`) until the vehicle confirms it with `PARAM_VALUE`.
+//!   A query on `<name>` with a payload does the same and replies the confirmed [`Param`].
+//!
+//! Sets are authorized as the `PARAM_SET` injected from zenoh (see [`crate::access`]).
+//!
+//! Every `PARAM_VALUE` seen on the broadcast channel updates the cache.
+//! Values are carried as `f32`, integer parameters use the C-cast convention of ArduPilot.
+//!
+//! Optional selector parameters: `timeout` (milliseconds), `retries` and `refresh`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mavio::dialects::common::{
+    enums::MavParamType,
+    messages::{ParamRequestList, ParamRequestRead, ParamSet, ParamValue},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+use zenoh::{
+    key_expr::{format::keformat, keyexpr},
+    query::Query,
+    sample::SampleKind,
+    Session,
+};
+
+use crate::{
+    access::{Access, PARAM_SET},
+    liveliness::ke_liveliness_params,
+    routing::Router,
+    service::{parameter, parse_target, recv_from, timeout_parameter},
+};
+
+/// Time to wait for a `PARAM_VALUE` before retransmitting a request.
+pub const DEFAULT_PARAM_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Number of retransmissions before giving up.
+pub const DEFAULT_PARAM_RETRIES: u8 = 3;
+/// Maximum length of a parameter name.
+const PARAM_ID_LEN: usize = 16;
+/// `param_index` used by autopilots when the index is unknown (e.g. in answer to `PARAM_SET`).
+const UNKNOWN_INDEX: u16 = u16::MAX;
+/// `MAV_PARAM_TYPE_UINT8` to `MAV_PARAM_TYPE_INT64`.
+const INTEGER_PARAM_TYPES: std::ops::RangeInclusive<u8> = 1..=8;
+/// Relative difference tolerated between a real value set and the one confirmed by the vehicle.
+const REAL_PARAM_TOLERANCE: f32 = 4.0 * f32::EPSILON;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Param {
+    pub name: String,
+    pub value: f32,
+    /// `MAV_PARAM_TYPE` value.
+    #[serde(rename = "type")]
+    pub param_type: u8,
+    pub index: u16,
+}
+
+impl From<&ParamValue> for Param {
+    fn from(value: &ParamValue) -> Self {
+        Self {
+            name: decode_param_id(&value.param_id),
+            value: value.param_value,
+            param_type: value.param_type as u8,
+            index: value.param_index,
+        }
+    }
+}
+
+/// Whether the `confirmed` value of a parameter of `MAV_PARAM_TYPE` `param_type` is the `requested` one. Integers
+/// are compared once truncated, as the vehicle stores them, reals up to their rounding.
+fn same_value(param_type: u8, confirmed: f32, requested: f32) -> bool {
+    if INTEGER_PARAM_TYPES.contains(&param_type) {
+        confirmed as i64 == requested as i64
+    } else {
+        (confirmed - requested).abs() <= REAL_PARAM_TOLERANCE * confirmed.abs().max(requested.abs())
+    }
+}
+
+/// New value of a parameter: either a bare number or `{"value": 1.0, "type": 9}`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ParamUpdate {
+    Value(f32),
+    Typed {
+        value: f32,
+        #[serde(rename = "type")]
+        param_type: Option<u8>,
+    },
+}
+
+/// Parameters known for a system/component.
+#[derive(Default, Debug)]
+struct ParamCache {
+    count: Option<u16>,
+    params: BTreeMap<String, Param>,
+}
+
+impl ParamCache {
+    fn update(&mut self, value: &ParamValue) {
+        let mut param = Param::from(value);
+        if param.index == UNKNOWN_INDEX {
+            if let Some(known) = self.params.get(&param.name) {
+                param.index = known.index;
+            }
+        } else {
+            self.count = Some(value.param_count);
+        }
+        self.params.insert(param.name.clone(), param);
+    }
+
+    /// Indexes of the full list that were never received.
+    fn missing(&self) -> Vec<u16> {
+        let Some(count) = self.count else {
+            return vec![];
+        };
+        let mut received = vec![false; count as usize];
+        for param in self.params.values() {
+            if let Some(r) = received.get_mut(param.index as usize) {
+                *r = true;
+            }
+        }
+        (0..count).filter(|i| !received[*i as usize]).collect()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.count.is_some() && self.missing().is_empty()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ParamService {
+    zsession: Arc<Session>,
+    router: Router,
+    access: Arc<Access>,
+    cache: Arc<Mutex<HashMap<(u8, u8), ParamCache>>>,
+}
+
+impl ParamService {
+    pub fn new(zsession: Arc<Session>, router: Router, access: Arc<Access>) -> Self {
+        Self {
+            zsession,
+            router,
+            access,
+            cache: Default::default(),
+        }
+    }
+
+    pub async fn run(self) {
+        let ke = keformat!(
+            ke_liveliness_params::formatter(),
+            zenoh_id = self.zsession.zid().into_keyexpr(),
+            system_id = "*",
+            component_id = "*",
+            name = "*",
+        )
+        .unwrap();
+        let queryable = match self.zsession.declare_queryable(ke.clone()).await {
+            Ok(queryable) => queryable,
+            Err(e) => {
+                error!("unable to declare parameters queryable on {ke}: {e}");
+                return;
+            }
+        };
+        let subscriber = match self.zsession.declare_subscriber(ke.clone()).await {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("unable to declare parameters subscriber on {ke}: {e}");
+                return;
+            }
+        };
+
+        // keep the cache up to date with every parameter exchanged on the MAVLink network
+        tokio::spawn(
+            self.clone()
+                .watch()
+                .instrument(debug_span!("mav_params_cache")),
+        );
+
+        info!("serving MAVLink parameters on {ke}");
+        loop {
+            tokio::select! {
+                Ok(query) = queryable.recv_async() => {
+                    let service = self.clone();
+                    tokio::spawn(
+                        async move {
+                            if let Err(e) = service.handle_query(&query).await {
+                                warn!("parameters query {} failed: {e}", query.selector());
+                                if let Err(e) = query.reply_err(e).await {
+                                    error!("failed to reply to parameters query: {e}");
+                                }
+                            }
+                        }
+                        .instrument(debug_span!("mav_params")),
+                    );
+                }
+                Ok(sample) = subscriber.recv_async() => {
+                    if sample.kind() != SampleKind::Put {
+                        continue;
+                    }
+                    let service = self.clone();
+                    tokio::spawn(
+                        async move {
+                            let ke = sample.key_expr().clone();
+                            let source = sample.source_info().source_id.map(|id| id.zid().to_string());
+                            let res = match serde_json::from_slice(&sample.payload().to_bytes()) {
+                                Ok(update) => {
+                                    service
+                                        .set(&ke, source.as_deref(), update, DEFAULT_PARAM_TIMEOUT, DEFAULT_PARAM_RETRIES)
+                                        .await
+                                }
+                                Err(e) => Err(e.to_string()),
+                            };
+                            match res {
+                                Ok(param) => info!("parameter {} set to {}", param.name, param.value),
+                                Err(e) => warn!("failed to set parameter {ke}: {e}"),
+                            }
+                        }
+                        .instrument(debug_span!("mav_params")),
+                    );
+                }
+                else => break,
+            }
+        }
+    }
+
+    async fn watch(self) {
+        let mut rx = self.router.subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(msg) => {
+                    if let Some(value) = msg.decode::<ParamValue>() {
+                        self.update_cache(
+                            msg.mav_frame.system_id(),
+                            msg.mav_frame.component_id(),
+                            &value,
+                        );
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!(
+                        "parameters cache lagged behind broadcast channel ({n} messages skipped)"
+                    );
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn update_cache(&self, system_id: u8, component_id: u8, value: &ParamValue) {
+        self.cache
+            .lock()
+            .unwrap()
+            .entry((system_id, component_id))
+            .or_default()
+            .update(value);
+    }
+
+    async fn handle_query(&self, query: &Query) -> Result<(), String> {
+        let timeout = timeout_parameter(query, "timeout", DEFAULT_PARAM_TIMEOUT)?;
+        let retries = parameter(query, "retries", DEFAULT_PARAM_RETRIES)?;
+
+        let ke = ke_liveliness_params::parse(query.key_expr()).map_err(|e| e.to_string())?;
+        let params = if let Some(payload) = query.payload() {
+            let update = serde_json::from_slice(&payload.to_bytes()).map_err(|e| e.to_string())?;
+            vec![
+                self.set(query.key_expr(), None, update, timeout, retries)
+                    .await?,
+            ]
+        } else if ke.name().as_str() == "*" {
+            let (system_id, component_id) = parse_target(ke.system_id(), ke.component_id())?;
+            let refresh = query.parameters().get("refresh").is_some();
+            self.list(system_id, component_id, refresh, timeout, retries)
+                .await?
+        } else {
+            let (system_id, component_id) = parse_target(ke.system_id(), ke.component_id())?;
+            vec![
+                self.read(
+                    system_id,
+                    component_id,
+                    ke.name().as_str(),
+                    timeout,
+                    retries,
+                )
+                .await?,
+            ]
+        };
+
+        for param in params {
+            let ke = keformat!(
+                ke_liveliness_params::formatter(),
+                zenoh_id = self.zsession.zid().into_keyexpr(),
+                system_id = ke.system_id(),
+                component_id = ke.component_id(),
+                name = param.name.as_str(),
+            )
+            .map_err(|e| e.to_string())?;
+            query
+                .reply(ke, serde_json::to_vec(&param).unwrap())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a single parameter from the vehicle.
+    async fn read(
+        &self,
+        system_id: u8,
+        component_id: u8,
+        name: &str,
+        timeout: Duration,
+        retries: u8,
+    ) -> Result<Param, String> {
+        let message = ParamRequestRead {
+            target_system: system_id,
+            target_component: component_id,
+            param_id: encode_param_id(name)?,
+            param_index: -1,
+        };
+
+        let mut rx = self.router.subscribe();
+        for _ in 0..=retries {
+            debug!("requesting parameter {name} from {system_id}/{component_id}");
+            self.router.send(system_id, component_id, &message)?;
+            let deadline = Instant::now() + timeout;
+            if let Some(value) = recv_from(
+                &mut rx,
+                system_id,
+                component_id,
+                deadline,
+                |v: &ParamValue| decode_param_id(&v.param_id) == name,
+            )
+            .await?
+            {
+                self.update_cache(system_id, component_id, &value);
+                return Ok(Param::from(&value));
+            }
+        }
+        Err(format!(
+            "no PARAM_VALUE from {system_id}/{component_id} for {name}"
+        ))
+    }
+
+    /// Returns every parameter of the vehicle, downloading the list if needed.
+    async fn list(
+        &self,
+        system_id: u8,
+        component_id: u8,
+        refresh: bool,
+        timeout: Duration,
+        retries: u8,
+    ) -> Result<Vec<Param>, String> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            let params = cache.entry((system_id, component_id)).or_default();
+            if refresh {
+                *params = ParamCache::default();
+            } else if params.is_complete() {
+                return Ok(params.params.values().cloned().collect());
+            }
+        }
+
+        let mut rx = self.router.subscribe();
+        debug!("requesting parameters list from {system_id}/{component_id}");
+        self.router.send(
+            system_id,
+            component_id,
+            &ParamRequestList {
+                target_system: system_id,
+                target_component: component_id,
+            },
+        )?;
+
+        let mut attempts = 0;
+        loop {
+            // receive values as long as the vehicle keeps sending them
+            let deadline = Instant::now() + timeout;
+            while let Some(value) = recv_from(
+                &mut rx,
+                system_id,
+                component_id,
+                deadline,
+                |_: &ParamValue| true,
+            )
+            .await?
+            {
+                self.update_cache(system_id, component_id, &value);
+                if self.is_complete(system_id, component_id) {
+                    return Ok(self.cached(system_id, component_id));
+                }
+            }
+
+            if attempts == retries {
+                let cache = self.cache.lock().unwrap();
+                let params = &cache[&(system_id, component_id)];
+                return Err(match params.count {
+                    Some(count) => format!(
+                        "{} of {count} parameters missing from {system_id}/{component_id}",
+                        params.missing().len()
+                    ),
+                    None => format!("no PARAM_VALUE from {system_id}/{component_id}"),
+                });
+            }
+            attempts += 1;
+
+            // recover missing indexes one by one, or restart the list if nothing was received
+            let missing = self.cache.lock().unwrap()[&(system_id, component_id)].missing();
+            if missing.is_empty() {
+                debug!("requesting parameters list from {system_id}/{component_id} again");
+                self.router.send(
+                    system_id,
+                    component_id,
+                    &ParamRequestList {
+                        target_system: system_id,
+                        target_component: component_id,
+                    },
+                )?;
+            } else {
+                debug!(
+                    "requesting {} missing parameters from {system_id}/{component_id}",
+                    missing.len()
+                );
+                for index in missing {
+                    self.router.send(
+                        system_id,
+                        component_id,
+                        &ParamRequestRead {
+                            target_system: system_id,
+                            target_component: component_id,
+                            param_id: [0; PARAM_ID_LEN],
+                            param_index: index as i16,
+                        },
+                    )?;
+                }
+            }
+        }
+    }
+
+    /// Sets a parameter, if authorized for `source`, and waits for the vehicle to confirm the new value.
+    async fn set(
+        &self,
+        ke: &zenoh::key_expr::KeyExpr<'_>,
+        source: Option<&str>,
+        update: ParamUpdate,
+        timeout: Duration,
+        retries: u8,
+    ) -> Result<Param, String> {
+        let key: &keyexpr = ke;
+        let ke = ke_liveliness_params::parse(ke).map_err(|e| e.to_string())?;
+        let (system_id, component_id) = parse_target(ke.system_id(), ke.component_id())?;
+        let name = ke.name().as_str();
+        if name == "*" {
+            return Err("cannot set every parameter at once".to_string());
+        }
+        self.access
+            .authorize_message(system_id, PARAM_SET, key, source)?;
+
+        let (value, param_type) = match update {
+            ParamUpdate::Value(value) => (value, None),
+            ParamUpdate::Typed { value, param_type } => (value, param_type),
+        };
+        // the type must match the one of the vehicle, learn it if unknown
+        let param_type =
+            match param_type.or_else(|| self.cached_type(system_id, component_id, name)) {
+                Some(param_type) => param_type,
+                None => {
+                    self.read(system_id, component_id, name, timeout, retries)
+                        .await?
+                        .param_type
+                }
+            };
+
+        let message = ParamSet {
+            target_system: system_id,
+            target_component: component_id,
+            param_id: encode_param_id(name)?,
+            param_value: value,
+            param_type: MavParamType::try_from(param_type)
+                .map_err(|_| format!("invalid parameter type {param_type}"))?,
+        };
+
+        let mut rx = self.router.subscribe();
+        for _ in 0..=retries {
+            debug!("setting parameter {name} of {system_id}/{component_id} to {value}");
+            self.router.send(system_id, component_id, &message)?;
+            let deadline = Instant::now() + timeout;
+            if let Some(confirmed) = recv_from(
+                &mut rx,
+                system_id,
+                component_id,
+                deadline,
+                |v: &ParamValue| decode_param_id(&v.param_id) == name,
+            )
+            .await?
+            {
+                self.update_cache(system_id, component_id, &confirmed);
+                let param = Param::from(&confirmed);
+                if !same_value(param.param_type, param.value, value) {
+                    return Err(format!(
+                        "vehicle rejected value {value} for {name} (current value: {})",
+                        param.value
+                    ));
+                }
+                return Ok(param);
+            }
+        }
+        Err(format!(
+            "no PARAM_VALUE from {system_id}/{component_id} confirming {name}"
+        ))
+    }
+
+    fn is_complete(&self, system_id: u8, component_id: u8) -> bool {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&(system_id, component_id))
+            .is_some_and(ParamCache::is_complete)
+    }
+
+    fn cached(&self, system_id: u8, component_id: u8) -> Vec<Param> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&(system_id, component_id))
+            .map(|c| c.params.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn cached_type(&self, system_id: u8, component_id: u8, name: &str) -> Option<u8> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&(system_id, component_id))
+            .and_then(|c| c.params.get(name))
+            .map(|p| p.param_type)
+    }
+}
+
+/// Parameter names are null-terminated, unless they are exactly 16 characters long.
+fn decode_param_id(param_id: &[u8; PARAM_ID_LEN]) -> String {
+    let len = param_id
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(PARAM_ID_LEN);
+    String::from_utf8_lossy(&param_id[..len]).into_owned()
+}
+
+fn encode_param_id(name: &str) -> Result<[u8; PARAM_ID_LEN], String> {
+    if name.len() > PARAM_ID_LEN {
+        return Err(format!(
+            "parameter name {name} is longer than {PARAM_ID_LEN} characters"
+        ));
+    }
+    let mut param_id = [0; PARAM_ID_LEN];
+    param_id[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(param_id)
+}
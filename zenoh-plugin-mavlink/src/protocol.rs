@@ -1,20 +1,69 @@
 //! Inner messaging protocol for MAVLink connections and broadcast channels.
 
-use mavio::{MavFrame};
+use std::time::Duration;
+
+use mavio::{
+    prelude::Versionless,
+    protocol::{MavLinkVersion, Payload},
+    MavFrame, Receiver,
+};
+
+/// Origin used for messages produced by the plugin itself or received from the Zenoh network.
+pub const ZENOH_ORIGIN: &str = "zenoh";
+
+pub(crate) const MAVLINK_V1_STX: u8 = 0xFE;
+const MAVLINK_V2_STX: u8 = 0xFD;
+pub(crate) const MAVLINK_IFLAG_SIGNED: u8 = 0x01;
+const MAVLINK_SIGNATURE_LEN: usize = 13;
+
+/// Returns the length of the raw MAVLink frame starting at `bytes[0]`, or `None` if it is not a frame start
+/// or if `bytes` is too short to tell.
+pub fn raw_frame_len(bytes: &[u8]) -> Option<usize> {
+    match bytes {
+        [MAVLINK_V1_STX, payload_len, ..] => Some(6 + *payload_len as usize + 2),
+        [MAVLINK_V2_STX, payload_len, incompat_flags, ..] => {
+            let signature_len = if incompat_flags & MAVLINK_IFLAG_SIGNED != 0 {
+                MAVLINK_SIGNATURE_LEN
+            } else {
+                0
+            };
+            Some(10 + *payload_len as usize + 2 + signature_len)
+        }
+        _ => None,
+    }
+}
+
+/// Splits back to back raw frames, returning them with the bytes left after the last complete frame.
+pub fn split_raw_frames(mut bytes: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let mut frames = Vec::new();
+    while let Some(len) = raw_frame_len(bytes).filter(|len| *len <= bytes.len()) {
+        frames.push(&bytes[..len]);
+        bytes = &bytes[len..];
+    }
+    (frames, bytes)
+}
+
+/// Parses a raw MAVLink (v1 or v2) frame.
+pub fn parse_raw_frame(bytes: &[u8]) -> mavio::errors::Result<MavFrame> {
+    let mut receiver = Receiver::<_, Versionless>::new(bytes);
+    Ok(receiver.recv()?.into_mav_frame())
+}
 
 #[derive(Clone, Debug)]
 pub struct Protocol {
     /// Source of the message (e.g connection endpoint such as `serial:/dev/USB0:115200` or some identifier like `zenoh`).
     pub origin: String,
+    /// Connection endpoint the message must be written to. If `None`, every connection (except the origin) writes it.
+    pub target: Option<String>,
     pub mav_frame: MavFrame,
     pub timestamp: u64,
 }
 
 impl Protocol {
     pub fn new(origin: &str, mav_frame: MavFrame) -> Self {
-
         Self {
             origin: origin.to_string(),
+            target: None,
             timestamp: chrono::Utc::now().timestamp_micros() as u64,
             mav_frame,
         }
@@ -23,8 +72,67 @@ impl Protocol {
     pub fn new_with_timestamp(timestamp: u64, origin: &str, mav_frame: MavFrame) -> Self {
         Self {
             origin: origin.to_string(),
+            target: None,
             timestamp,
             mav_frame,
         }
     }
+
+    /// Restricts the message to a single connection endpoint.
+    pub fn with_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Returns `true` if the connection identified by `endpoint` must write this message.
+    pub fn is_for(&self, endpoint: &str) -> bool {
+        self.origin != endpoint && self.target.as_deref().map_or(true, |t| t == endpoint)
+    }
+
+    /// Time elapsed since the message was received (or produced).
+    pub fn age(&self) -> Duration {
+        let now = chrono::Utc::now().timestamp_micros() as u64;
+        Duration::from_micros(now.saturating_sub(self.timestamp))
+    }
+
+    /// Serializes the MAVLink frame as sent on the wire, with its signature if signed.
+    pub fn raw_frame(&self) -> Vec<u8> {
+        let raw_frame = self.mav_frame.clone().into_versionless();
+        let header = raw_frame.header();
+        let header_bytes = header.decode();
+        let payload_bytes = raw_frame.payload().bytes();
+        let checksum = raw_frame.checksum();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(header_bytes.as_slice());
+        bytes.extend_from_slice(payload_bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        if let Some(signature) = raw_frame.signature() {
+            bytes.extend_from_slice(&signature.to_byte_array());
+        }
+
+        bytes
+    }
+
+    /// Length of [`raw_frame`](Self::raw_frame), without serializing the frame.
+    pub fn raw_frame_len(&self) -> usize {
+        let header_len = match self.mav_frame.version() {
+            MavLinkVersion::V1 => 6,
+            MavLinkVersion::V2 => 10,
+        };
+        let signature_len = if self.mav_frame.signature().is_some() {
+            MAVLINK_SIGNATURE_LEN
+        } else {
+            0
+        };
+        header_len + self.mav_frame.payload().bytes().len() + 2 + signature_len
+    }
+
+    /// Decodes the frame payload as message `M`. Returns `None` if the frame carries another message.
+    pub fn decode<M>(&self) -> Option<M>
+    where
+        M: for<'a> TryFrom<&'a Payload>,
+    {
+        M::try_from(self.mav_frame.payload()).ok()
+    }
 }
@@ -0,0 +1,122 @@
+//! Zenoh quality of service of the frames published on the `out` key expression, per message.
+//!
+//! Each [`QosRule`] gets its own publisher, so e.g. `COMMAND_ACK` and `STATUSTEXT` can be sent with a higher
+//! priority than a flood of `HIGHRES_IMU`. Frames of messages not listed in any rule use Zenoh defaults.
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde::Deserialize;
+use zenoh::{
+    key_expr::OwnedKeyExpr,
+    pubsub::Publisher,
+    qos::{CongestionControl, Priority},
+    Result as ZResult, Session,
+};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QosPriority {
+    RealTime,
+    InteractiveHigh,
+    InteractiveLow,
+    DataHigh,
+    Data,
+    DataLow,
+    Background,
+}
+
+impl From<QosPriority> for Priority {
+    fn from(value: QosPriority) -> Self {
+        match value {
+            QosPriority::RealTime => Priority::RealTime,
+            QosPriority::InteractiveHigh => Priority::InteractiveHigh,
+            QosPriority::InteractiveLow => Priority::InteractiveLow,
+            QosPriority::DataHigh => Priority::DataHigh,
+            QosPriority::Data => Priority::Data,
+            QosPriority::DataLow => Priority::DataLow,
+            QosPriority::Background => Priority::Background,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QosCongestionControl {
+    /// Drop frames when the network is congested.
+    Drop,
+    /// Wait for the network to accept frames (never drops, but may slow down every publication).
+    Block,
+}
+
+impl From<QosCongestionControl> for CongestionControl {
+    fn from(value: QosCongestionControl) -> Self {
+        match value {
+            QosCongestionControl::Drop => CongestionControl::Drop,
+            QosCongestionControl::Block => CongestionControl::Block,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct QosRule {
+    /// Message ids this rule applies to.
+    pub messages: Vec<u32>,
+    #[serde(default)]
+    pub priority: Option<QosPriority>,
+    #[serde(default)]
+    pub congestion_control: Option<QosCongestionControl>,
+    /// Send frames immediately, without waiting to be batched with others by Zenoh.
+    #[serde(default)]
+    pub express: Option<bool>,
+}
+
+/// Publishers of the `out` key expression: Zenoh defaults first, then one per [`QosRule`].
+pub(crate) struct Publishers {
+    publishers: Vec<Publisher<'static>>,
+    /// Message id => index in `publishers`.
+    rules: HashMap<u32, usize>,
+}
+
+impl Publishers {
+    pub async fn declare(
+        zsession: &Arc<Session>,
+        ke: OwnedKeyExpr,
+        rules: &[QosRule],
+    ) -> ZResult<Self> {
+        let mut publishers = vec![zsession.declare_publisher(ke.clone()).await?];
+        let mut indexes = HashMap::new();
+        for rule in rules {
+            let mut builder = zsession.declare_publisher(ke.clone());
+            if let Some(priority) = rule.priority {
+                builder = builder.priority(priority.into());
+            }
+            if let Some(congestion_control) = rule.congestion_control {
+                builder = builder.congestion_control(congestion_control.into());
+            }
+            if let Some(express) = rule.express {
+                builder = builder.express(express);
+            }
+            publishers.push(builder.await?);
+
+            // the first rule listing a message wins
+            for message_id in &rule.messages {
+                indexes.entry(*message_id).or_insert(publishers.len() - 1);
+            }
+        }
+
+        Ok(Self {
+            publishers,
+            rules: indexes,
+        })
+    }
+
+    /// Index of the publisher frames of `message_id` are published with.
+    pub fn index(&self, message_id: u32) -> usize {
+        self.rules.get(&message_id).copied().unwrap_or_default()
+    }
+
+    pub fn get(&self, index: usize) -> &Publisher<'static> {
+        &self.publishers[index]
+    }
+}
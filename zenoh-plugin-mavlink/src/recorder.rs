@@ -0,0 +1,197 @@
+//! Records every frame of the broadcast channel to QGroundControl-compatible `.tlog` files.
+//!
+//! Each record of a `.tlog` file is the big-endian microsecond timestamp of the frame ([`Protocol::timestamp`])
+//! followed by the raw frame. Next to each `.tlog` file, a `.origins.csv` sidecar lists the origin
+//! (connection endpoint or `zenoh`) of each record, in the same order.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{error, info, warn};
+
+use crate::protocol::Protocol;
+
+pub const DEFAULT_RECORDER_DIRECTORY: &str = "tlogs";
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RecorderConfig {
+    /// Directory the `.tlog` files are written to (created if missing).
+    #[serde(default = "default_directory")]
+    pub directory: PathBuf,
+    /// Start a new file once the current one reaches this size (in bytes).
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    /// Start a new file once the current one is older than this duration (in seconds).
+    #[serde(default)]
+    pub max_file_duration: Option<f32>,
+    /// Write one file per vehicle (system id) instead of a single file.
+    #[serde(default)]
+    pub per_vehicle: bool,
+    /// Write the origin of each frame in a `.origins.csv` sidecar file.
+    #[serde(default = "default_sidecar")]
+    pub sidecar: bool,
+}
+
+fn default_directory() -> PathBuf {
+    PathBuf::from(DEFAULT_RECORDER_DIRECTORY)
+}
+
+fn default_sidecar() -> bool {
+    true
+}
+
+/// A `.tlog` file being written, with its sidecar.
+struct TlogFile {
+    tlog: BufWriter<File>,
+    sidecar: Option<BufWriter<File>>,
+    size: u64,
+    opened: Instant,
+}
+
+impl TlogFile {
+    fn create(config: &RecorderConfig, name: &str, timestamp: u64) -> io::Result<Self> {
+        let date = chrono::DateTime::from_timestamp_micros(timestamp as i64)
+            .unwrap_or_default()
+            .format("%Y%m%d-%H%M%S%.6f");
+        let path = config.directory.join(format!("{name}-{date}.tlog"));
+        info!("recording to {}", path.display());
+
+        let tlog = BufWriter::new(File::create(&path)?);
+        let sidecar = if config.sidecar {
+            let mut sidecar = BufWriter::new(File::create(path.with_extension("origins.csv"))?);
+            writeln!(
+                sidecar,
+                "timestamp_us,system_id,component_id,message_id,origin"
+            )?;
+            Some(sidecar)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            tlog,
+            sidecar,
+            size: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, msg: &Protocol) -> io::Result<()> {
+        let frame = msg.raw_frame();
+        self.tlog.write_all(&msg.timestamp.to_be_bytes())?;
+        self.tlog.write_all(&frame)?;
+        self.size += 8 + frame.len() as u64;
+
+        if let Some(sidecar) = &mut self.sidecar {
+            writeln!(
+                sidecar,
+                "{},{},{},{},{}",
+                msg.timestamp,
+                msg.mav_frame.system_id(),
+                msg.mav_frame.component_id(),
+                msg.mav_frame.message_id(),
+                msg.origin
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tlog.flush()?;
+        if let Some(sidecar) = &mut self.sidecar {
+            sidecar.flush()?;
+        }
+        Ok(())
+    }
+
+    fn is_full(&self, config: &RecorderConfig) -> bool {
+        config.max_file_size.is_some_and(|max| self.size >= max)
+            || config
+                .max_file_duration
+                .is_some_and(|max| self.opened.elapsed() >= Duration::from_secs_f32(max))
+    }
+}
+
+pub struct Recorder {
+    config: RecorderConfig,
+    /// Files being written, by system id (or `None` if not recording per vehicle).
+    files: HashMap<Option<u8>, TlogFile>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> Self {
+        Self {
+            config,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Record every message of the broadcast channel. Blocking, must run on a dedicated thread.
+    pub fn run(mut self, mut rx: Receiver<Protocol>) {
+        if let Err(e) = fs::create_dir_all(&self.config.directory) {
+            error!(
+                "unable to create recorder directory {}: {e}",
+                self.config.directory.display()
+            );
+            return;
+        }
+
+        loop {
+            match rx.blocking_recv() {
+                Ok(msg) => {
+                    if let Err(e) = self.record(&msg) {
+                        error!("failed to record frame: {e}");
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("recorder lagged behind broadcast channel ({n} frames not recorded)");
+                }
+                Err(RecvError::Closed) => break,
+            }
+
+            // flush once the burst of frames is written
+            if rx.is_empty() {
+                for file in self.files.values_mut() {
+                    if let Err(e) = file.flush() {
+                        error!("failed to flush recording: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, msg: &Protocol) -> io::Result<()> {
+        let system_id = msg.mav_frame.system_id();
+        let key = self.config.per_vehicle.then_some(system_id);
+
+        if self
+            .files
+            .get(&key)
+            .is_some_and(|f| f.is_full(&self.config))
+        {
+            if let Some(mut file) = self.files.remove(&key) {
+                file.flush()?;
+            }
+        }
+
+        let file = match self.files.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let name = match key {
+                    Some(system_id) => format!("sys{system_id}"),
+                    None => "mavlink".to_string(),
+                };
+                entry.insert(TlogFile::create(&self.config, &name, msg.timestamp)?)
+            }
+        };
+        file.write(msg)
+    }
+}
@@ -0,0 +1,160 @@
+//! Virtual MAVLink connection replaying a `.tlog` file (endpoint `replay:<path>`).
+//!
+//! Frames are injected in the broadcast channel at their original pacing, `speed` times faster,
+//! or as fast as possible if `speed` is 0. Frames written to the connection are discarded.
+
+use std::{io, path::Path, time::Duration};
+
+use mavio::MavFrame;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    sync::broadcast::Sender,
+    time::Instant,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::protocol::{parse_raw_frame, raw_frame_len, Protocol};
+
+/// Endpoint prefix of replay connections.
+pub const REPLAY_PREFIX: &str = "replay:";
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayOptions {
+    /// Replay speed factor (1 = original pacing, 0 = as fast as possible).
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    /// Start again from the beginning once the end of the file is reached.
+    #[serde(default, rename = "loop")]
+    pub looping: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: default_speed(),
+            looping: false,
+        }
+    }
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+/// Reads the records (timestamp in microseconds, frame) of a `.tlog` file, one at a time.
+pub struct TlogReader<R> {
+    reader: R,
+    /// Offset of the next record.
+    offset: u64,
+}
+
+impl<R: AsyncRead + Unpin> TlogReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, offset: 0 }
+    }
+
+    /// Returns the next record, or `None` at the end of the file (or at the first invalid or truncated record).
+    pub async fn next_record(&mut self) -> io::Result<Option<(u64, MavFrame)>> {
+        loop {
+            let offset = self.offset;
+            let mut timestamp = [0u8; 8];
+            match self.read(&mut timestamp).await? {
+                Read::Done => {}
+                Read::End => return Ok(None),
+                Read::Truncated => return Ok(truncated(offset)),
+            }
+            // enough to know the length of any frame
+            let mut frame = vec![0u8; 3];
+            if self.read(&mut frame).await? != Read::Done {
+                return Ok(truncated(offset));
+            }
+            let Some(len) = raw_frame_len(&frame) else {
+                warn!("invalid tlog record at offset {offset}, stopping there");
+                return Ok(None);
+            };
+            frame.resize(len, 0);
+            if self.read(&mut frame[3..]).await? != Read::Done {
+                return Ok(truncated(offset));
+            }
+            self.offset += 8 + len as u64;
+
+            match parse_raw_frame(&frame) {
+                Ok(mav_frame) => return Ok(Some((u64::from_be_bytes(timestamp), mav_frame))),
+                Err(e) => debug!("skipping invalid frame at offset {offset}: {e}"),
+            }
+        }
+    }
+
+    /// Fills `buf`, telling whether the end of the file was reached before (or while) doing so.
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<Read> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]).await? {
+                0 if filled == 0 => return Ok(Read::End),
+                0 => return Ok(Read::Truncated),
+                n => filled += n,
+            }
+        }
+        Ok(Read::Done)
+    }
+}
+
+#[derive(PartialEq)]
+enum Read {
+    Done,
+    End,
+    Truncated,
+}
+
+fn truncated(offset: u64) -> Option<(u64, MavFrame)> {
+    warn!("truncated tlog record at offset {offset}, stopping there");
+    None
+}
+
+/// Replays the `.tlog` file at `path` in the broadcast channel as `endpoint`, reading it as it goes.
+pub async fn replay(
+    endpoint: &str,
+    path: &Path,
+    options: &ReplayOptions,
+    tx: &Sender<Protocol>,
+) -> io::Result<()> {
+    info!("replaying {}", path.display());
+
+    loop {
+        let mut records = TlogReader::new(BufReader::new(File::open(path).await?));
+        let mut frames = 0;
+        // timestamp of the first record, and when it was replayed
+        let mut first = None;
+        while let Some((timestamp, mav_frame)) = records.next_record().await? {
+            let (first_timestamp, start) = *first.get_or_insert((timestamp, Instant::now()));
+            if options.speed > 0.0 {
+                let offset =
+                    timestamp.saturating_sub(first_timestamp) as f64 / options.speed as f64;
+                let offset = Duration::from_micros(offset as u64);
+                tokio::time::sleep(offset.saturating_sub(start.elapsed())).await;
+            } else {
+                tokio::task::yield_now().await;
+            }
+
+            if let Err(e) = tx.send(Protocol::new(endpoint, mav_frame)) {
+                error!("could not send broadcast message: {e}");
+            }
+            frames += 1;
+        }
+        if frames == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no MAVLink frame in {}", path.display()),
+            ));
+        }
+
+        if !options.looping {
+            info!("end of replay ({frames} frames)");
+            return Ok(());
+        }
+        debug!("looping replay");
+    }
+}
@@ -0,0 +1,173 @@
+//! Tracks the MAVLink connection each remote system/component was last heard on.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use mavio::{dialects::common::messages::Heartbeat, protocol::MavLinkVersion, Message};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver, Sender},
+    watch,
+};
+use tracing::{debug, error, trace};
+
+use crate::{
+    component::Component,
+    config::Config,
+    mavlink_connection::MAVLinkConnection,
+    protocol::{Protocol, ZENOH_ORIGIN},
+};
+
+/// `MAV_AUTOPILOT_INVALID`, used in heartbeats of components that are not flight controllers.
+pub(crate) const MAV_AUTOPILOT_INVALID: u8 = 8;
+
+#[derive(Clone, Default, Debug)]
+pub struct RoutingTable {
+    routes: Arc<RwLock<HashMap<(u8, u8), String>>>,
+    autopilots: Arc<RwLock<HashMap<u8, u8>>>,
+}
+
+impl RoutingTable {
+    /// Records the connection `msg` was received from.
+    /// Messages produced by the plugin or received from Zenoh are not routes.
+    pub fn update(&self, msg: &Protocol) {
+        if msg.origin == ZENOH_ORIGIN {
+            return;
+        }
+
+        let key = (msg.mav_frame.system_id(), msg.mav_frame.component_id());
+        if let Some(heartbeat) = msg.decode::<Heartbeat>() {
+            let autopilot = heartbeat.autopilot as u8;
+            if autopilot != MAV_AUTOPILOT_INVALID {
+                self.autopilots.write().unwrap().insert(key.0, autopilot);
+            }
+        }
+        if self.routes.read().unwrap().get(&key) == Some(&msg.origin) {
+            return;
+        }
+
+        debug!(
+            "system {}/{} is reachable through {}",
+            key.0, key.1, msg.origin
+        );
+        self.routes.write().unwrap().insert(key, msg.origin.clone());
+    }
+
+    /// Returns the connection endpoint a system/component was last heard on.
+    /// Falls back to any component of the same system if the component itself was never heard.
+    pub fn lookup(&self, system_id: u8, component_id: u8) -> Option<String> {
+        let routes = self.routes.read().unwrap();
+        routes.get(&(system_id, component_id)).cloned().or_else(|| {
+            routes
+                .iter()
+                .find(|((sys, _), _)| *sys == system_id)
+                .map(|(_, endpoint)| endpoint.clone())
+        })
+    }
+
+    /// Returns the `MAV_AUTOPILOT` type a system advertised in its heartbeats.
+    pub fn autopilot(&self, system_id: u8) -> Option<u8> {
+        self.autopilots.read().unwrap().get(&system_id).copied()
+    }
+
+    /// Keep the routing table up to date with every message from the broadcast channel.
+    pub async fn run(self, mut rx: Receiver<Protocol>) {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => self.update(&msg),
+                Err(RecvError::Lagged(n)) => {
+                    error!("routing table lagged behind broadcast channel ({n} messages skipped)");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// Sends messages originated by the plugin to remote systems.
+#[derive(Clone, Debug)]
+pub struct Router {
+    pub component: Arc<Component>,
+    pub routes: RoutingTable,
+    /// Latest configuration, with the MAVLink connections.
+    config: watch::Receiver<Arc<Config>>,
+    channel: Sender<Protocol>,
+}
+
+impl Router {
+    pub fn new(
+        component: Arc<Component>,
+        routes: RoutingTable,
+        config: watch::Receiver<Arc<Config>>,
+        channel: Sender<Protocol>,
+    ) -> Self {
+        Self {
+            component,
+            routes,
+            config,
+            channel,
+        }
+    }
+
+    /// The latest configuration.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// Watch the configuration, e.g. to apply the settings of reloaded connections.
+    pub fn watch_config(&self) -> watch::Receiver<Arc<Config>> {
+        self.config.clone()
+    }
+
+    /// Returns the `MAV_AUTOPILOT` type a system advertised in its heartbeats.
+    pub fn autopilot(&self, system_id: u8) -> Option<u8> {
+        self.routes.autopilot(system_id)
+    }
+
+    /// Subscribe to the broadcast channel, e.g. to wait for the answer to a message about to be sent.
+    pub fn subscribe(&self) -> Receiver<Protocol> {
+        self.channel.subscribe()
+    }
+
+    /// Sends `message` through the connection `system_id`/`component_id` was last heard on.
+    /// If the system was never heard, the message is written to every connection.
+    pub fn send<M: Message>(
+        &self,
+        system_id: u8,
+        component_id: u8,
+        message: &M,
+    ) -> Result<(), String> {
+        match self.routes.lookup(system_id, component_id) {
+            Some(endpoint) => {
+                trace!("sending message to {system_id}/{component_id} through {endpoint}");
+                self.send_to(&endpoint, message)
+            }
+            None => {
+                trace!("sending message to {system_id}/{component_id} through every connection");
+                self.config()
+                    .mavlink_connections
+                    .iter()
+                    .try_for_each(|c| self.send_to(&c.endpoint, message))
+            }
+        }
+    }
+
+    /// Sends `message` through the connection identified by `endpoint`.
+    pub fn send_to<M: Message>(&self, endpoint: &str, message: &M) -> Result<(), String> {
+        let version = self
+            .config()
+            .mavlink_connections
+            .iter()
+            .find(|c| c.endpoint == endpoint)
+            .map_or(MavLinkVersion::V2, MAVLinkConnection::frame_version);
+        let frame = self
+            .component
+            .versioned_frame(endpoint, version, message)
+            .map_err(|e| e.to_string())?;
+        self.channel
+            .send(Protocol::new(ZENOH_ORIGIN, frame).with_target(Some(endpoint.to_string())))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
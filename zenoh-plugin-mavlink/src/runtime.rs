@@ -0,0 +1,95 @@
+//! Async runtimes of the plugin.
+//!
+//! The plugin runs on its own multi-threaded runtime (`work_thread_num` workers, up to `max_block_thread_num`
+//! blocking threads), named [`WORKER_THREAD_NAME`], both in zenohd and in the standalone bridge. With
+//! `serial_runtime`, the serial connections run on a dedicated single-threaded runtime, on a thread named
+//! [`SERIAL_THREAD_NAME`] that can be pinned to a CPU, so that their I/O is not delayed by the other tasks.
+
+use std::sync::mpsc;
+
+use serde::Deserialize;
+use tokio::{runtime::Handle, sync::oneshot};
+use tracing::{info, warn};
+
+pub const WORKER_THREAD_NAME: &str = "mavlink-worker";
+pub const SERIAL_THREAD_NAME: &str = "mavlink-serial";
+
+#[derive(Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SerialRuntimeConfig {
+    /// CPU the serial I/O thread is pinned to (Linux only).
+    #[serde(default)]
+    pub cpu: Option<usize>,
+}
+
+/// The dedicated runtime of the serial connections, shut down when dropped.
+pub struct SerialRuntime {
+    handle: Handle,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl SerialRuntime {
+    pub fn start(config: &SerialRuntimeConfig) -> std::io::Result<Self> {
+        let (handle_tx, handle_rx) = mpsc::sync_channel(1);
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let cpu = config.cpu;
+        std::thread::Builder::new()
+            .name(SERIAL_THREAD_NAME.to_string())
+            .spawn(move || {
+                if let Some(cpu) = cpu {
+                    match pin_to_cpu(cpu) {
+                        Ok(()) => info!("serial I/O thread pinned to CPU {cpu}"),
+                        Err(e) => warn!("unable to pin serial I/O thread to CPU {cpu}: {e}"),
+                    }
+                }
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = handle_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = handle_tx.send(Ok(runtime.handle().clone()));
+                // run until the SerialRuntime is dropped
+                let _ = runtime.block_on(shutdown_rx);
+            })?;
+        let handle = handle_rx
+            .recv()
+            .map_err(|_| std::io::Error::other("serial I/O thread ended"))??;
+        Ok(Self {
+            handle,
+            _shutdown: shutdown,
+        })
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_cpu(cpu: usize) -> std::io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(std::io::Error::other(format!("no CPU {cpu}")));
+    }
+    // SAFETY: the set is initialized by CPU_ZERO and only used for the current thread
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_cpu(_cpu: usize) -> std::io::Result<()> {
+    Err(std::io::Error::other(
+        "CPU pinning is only supported on Linux",
+    ))
+}
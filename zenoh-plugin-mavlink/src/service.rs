@@ -0,0 +1,85 @@
+//! Helpers shared by the queryables serving MAVLink microservices.
+
+use std::{str::FromStr, time::Duration};
+
+use mavio::protocol::Payload;
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time::Instant,
+};
+use tracing::warn;
+use zenoh::{key_expr::keyexpr, query::Query};
+
+use crate::protocol::Protocol;
+
+/// Parses the `<system_id>/<component_id>` chunks of a microservice key expression.
+pub(crate) fn parse_target(
+    system_id: &keyexpr,
+    component_id: &keyexpr,
+) -> Result<(u8, u8), String> {
+    let system_id = system_id
+        .as_str()
+        .parse()
+        .map_err(|_| format!("invalid system id: {system_id}"))?;
+    let component_id = component_id
+        .as_str()
+        .parse()
+        .map_err(|_| format!("invalid component id: {component_id}"))?;
+    Ok((system_id, component_id))
+}
+
+/// Returns the selector parameter `name` of `query`, or `default` if absent.
+pub(crate) fn parameter<T: FromStr>(query: &Query, name: &str, default: T) -> Result<T, String> {
+    match query.parameters().get(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("invalid {name}: {value}")),
+        None => Ok(default),
+    }
+}
+
+/// Returns the selector parameter `name` of `query` in milliseconds, or `default` if absent.
+pub(crate) fn timeout_parameter(
+    query: &Query,
+    name: &str,
+    default: Duration,
+) -> Result<Duration, String> {
+    parameter(query, name, default.as_millis() as u64).map(Duration::from_millis)
+}
+
+/// Waits until `deadline` for a message `M` from `system_id`/`component_id` matching `filter`. A `component_id` of 0
+/// (a request broadcast to every component of the system) matches any component.
+/// Returns `Ok(None)` on timeout.
+pub(crate) async fn recv_from<M, F>(
+    rx: &mut Receiver<Protocol>,
+    system_id: u8,
+    component_id: u8,
+    deadline: Instant,
+    filter: F,
+) -> Result<Option<M>, String>
+where
+    M: for<'a> TryFrom<&'a Payload>,
+    F: Fn(&M) -> bool,
+{
+    loop {
+        let msg = match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(msg)) => msg,
+            Ok(Err(RecvError::Lagged(n))) => {
+                warn!("lagged behind broadcast channel ({n} messages skipped)");
+                continue;
+            }
+            Ok(Err(RecvError::Closed)) => return Err("broadcast channel closed".to_string()),
+            Err(_) => return Ok(None),
+        };
+        if msg.mav_frame.system_id() != system_id
+            || (component_id != 0 && msg.mav_frame.component_id() != component_id)
+        {
+            continue;
+        }
+        if let Some(message) = msg.decode::<M>() {
+            if filter(&message) {
+                return Ok(Some(message));
+            }
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Simulated vehicle exposed as a virtual MAVLink connection (endpoint `sim:<system_id>`).
+//!
+//! The vehicle streams `HEARTBEAT`, `SYS_STATUS` (battery), `GPS_RAW_INT`, `ATTITUDE` and
+//! `GLOBAL_POSITION_INT` at configurable rates, acknowledges commands (arming, `SET_MESSAGE_INTERVAL`)
+//! and serves its parameters, so the plugin can be exercised without hardware or SITL.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    time::{Duration, Instant},
+};
+
+use mavio::dialects::common::{
+    enums::{
+        GpsFixType, MavAutopilot, MavCmd, MavModeFlag, MavParamType, MavResult, MavState, MavType,
+    },
+    messages::{
+        Attitude, CommandAck, CommandLong, GlobalPositionInt, GpsRawInt, Heartbeat,
+        ParamRequestList, ParamRequestRead, ParamSet, ParamValue, SysStatus,
+    },
+};
+use mavio::Message;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{error::RecvError, Receiver, Sender};
+use tracing::{debug, error, info, warn};
+
+use crate::{component::Component, protocol::Protocol};
+
+/// Endpoint prefix of simulated vehicles.
+pub const SIM_PREFIX: &str = "sim:";
+
+/// Resolution of the simulation clock.
+const TICK: Duration = Duration::from_millis(10);
+/// Home position of the simulated vehicle (degrees * 1e7, millimeters).
+const HOME: (i32, i32, i32) = (473_977_420, 85_455_940, 488_000);
+/// Radius (in degrees * 1e7) of the circle flown by the simulated vehicle.
+const CIRCLE_RADIUS: f64 = 2_000.0;
+/// Battery capacity drained per second, in percent.
+const BATTERY_DRAIN: f32 = 1.0 / 60.0;
+
+const HEARTBEAT_ID: u32 = 0;
+const SYS_STATUS_ID: u32 = 1;
+const GPS_RAW_INT_ID: u32 = 24;
+const ATTITUDE_ID: u32 = 30;
+const GLOBAL_POSITION_INT_ID: u32 = 33;
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SimOptions {
+    #[serde(default = "default_component_id")]
+    pub component_id: u8,
+    /// Message id => rate in Hz of the streamed messages, 0 to not stream it.
+    #[serde(default = "default_rates")]
+    pub rates: BTreeMap<u32, f32>,
+    /// Parameters of the vehicle, as `REAL32` values.
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+}
+
+impl Default for SimOptions {
+    fn default() -> Self {
+        Self {
+            component_id: default_component_id(),
+            rates: default_rates(),
+            params: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_component_id() -> u8 {
+    1
+}
+
+fn default_rates() -> BTreeMap<u32, f32> {
+    BTreeMap::from([
+        (HEARTBEAT_ID, 1.0),
+        (SYS_STATUS_ID, 1.0),
+        (GPS_RAW_INT_ID, 5.0),
+        (ATTITUDE_ID, 10.0),
+        (GLOBAL_POSITION_INT_ID, 5.0),
+    ])
+}
+
+/// Period of a stream at `rate` (in Hz).
+pub(crate) fn rate_period(rate: f32) -> Result<Duration, String> {
+    if !(rate > 0.0 && rate.is_finite()) {
+        return Err(format!("invalid rate {rate} Hz"));
+    }
+    Duration::try_from_secs_f32(1.0 / rate).map_err(|_| format!("invalid rate {rate} Hz"))
+}
+
+struct SimVehicle<'a> {
+    endpoint: &'a str,
+    component: Component,
+    tx: &'a Sender<Protocol>,
+    boot: Instant,
+    armed: bool,
+    /// Message id => (period, next emission).
+    streams: BTreeMap<u32, (Duration, Instant)>,
+    params: BTreeMap<String, f32>,
+}
+
+impl SimVehicle<'_> {
+    fn send<M: Message>(&self, message: &M) {
+        match self.component.frame(self.endpoint, message) {
+            Ok(frame) => {
+                if let Err(e) = self.tx.send(Protocol::new(self.endpoint, frame)) {
+                    error!("could not send broadcast message: {e}");
+                }
+            }
+            Err(e) => error!("failed to build simulated frame: {e}"),
+        }
+    }
+
+    /// Streams `message_id` at `rate` (in Hz), or stops streaming it if `rate` is 0.
+    fn set_rate(&mut self, message_id: u32, rate: f32) -> Result<(), String> {
+        if rate == 0.0 {
+            self.streams.remove(&message_id);
+        } else {
+            let period = rate_period(rate)?;
+            self.streams.insert(message_id, (period, Instant::now()));
+        }
+        Ok(())
+    }
+
+    /// Emits every stream that is due.
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let due: Vec<u32> = self
+            .streams
+            .iter_mut()
+            .filter(|(_, (_, next))| *next <= now)
+            .map(|(id, (period, next))| {
+                *next += *period;
+                if *next < now {
+                    *next = now + *period;
+                }
+                *id
+            })
+            .collect();
+        for message_id in due {
+            self.emit(message_id);
+        }
+    }
+
+    fn emit(&self, message_id: u32) {
+        let elapsed = self.boot.elapsed();
+        let time_boot_ms = elapsed.as_millis() as u32;
+        let t = elapsed.as_secs_f64();
+        // the vehicle flies a circle of one minute around its home position
+        let angle = t * std::f64::consts::TAU / 60.0;
+        let lat = HOME.0 + (CIRCLE_RADIUS * angle.cos()) as i32;
+        let lon = HOME.1 + (CIRCLE_RADIUS * angle.sin()) as i32;
+
+        match message_id {
+            HEARTBEAT_ID => {
+                let mut base_mode = MavModeFlag::CUSTOM_MODE_ENABLED;
+                if self.armed {
+                    base_mode |= MavModeFlag::SAFETY_ARMED;
+                }
+                self.send(&Heartbeat {
+                    type_: MavType::try_from(self.component.mav_type).unwrap_or_default(),
+                    autopilot: MavAutopilot::Generic,
+                    base_mode,
+                    system_status: MavState::Active,
+                    mavlink_version: 3,
+                    ..Default::default()
+                });
+            }
+            SYS_STATUS_ID => {
+                let remaining = (100.0 - elapsed.as_secs_f32() * BATTERY_DRAIN).max(0.0);
+                self.send(&SysStatus {
+                    voltage_battery: (12_600.0 - (100.0 - remaining) * 20.0) as u16,
+                    current_battery: if self.armed { 1_500 } else { 50 },
+                    battery_remaining: remaining as i8,
+                    ..Default::default()
+                });
+            }
+            GPS_RAW_INT_ID => {
+                self.send(&GpsRawInt {
+                    time_usec: elapsed.as_micros() as u64,
+                    fix_type: GpsFixType::try_from(3).unwrap_or_default(),
+                    lat,
+                    lon,
+                    alt: HOME.2,
+                    eph: 100,
+                    epv: 150,
+                    vel: 500,
+                    cog: ((angle.to_degrees() + 90.0).rem_euclid(360.0) * 100.0) as u16,
+                    satellites_visible: 12,
+                    ..Default::default()
+                });
+            }
+            ATTITUDE_ID => {
+                self.send(&Attitude {
+                    time_boot_ms,
+                    roll: (0.1 * (t * 0.5).sin()) as f32,
+                    pitch: (0.05 * (t * 0.3).sin()) as f32,
+                    yaw: ((angle + std::f64::consts::FRAC_PI_2) % std::f64::consts::TAU
+                        - std::f64::consts::PI) as f32,
+                    rollspeed: (0.05 * (t * 0.5).cos()) as f32,
+                    pitchspeed: (0.015 * (t * 0.3).cos()) as f32,
+                    yawspeed: (std::f64::consts::TAU / 60.0) as f32,
+                });
+            }
+            GLOBAL_POSITION_INT_ID => {
+                self.send(&GlobalPositionInt {
+                    time_boot_ms,
+                    lat,
+                    lon,
+                    alt: HOME.2,
+                    relative_alt: 0,
+                    vx: (-500.0 * angle.sin()) as i16,
+                    vy: (500.0 * angle.cos()) as i16,
+                    vz: 0,
+                    hdg: ((angle.to_degrees() + 90.0).rem_euclid(360.0) * 100.0) as u16,
+                });
+            }
+            _ => debug!("message {message_id} is not simulated"),
+        }
+    }
+
+    fn param_value(&self, index: usize, name: &str, value: f32) {
+        let mut param_id = [0u8; 16];
+        let len = name.len().min(param_id.len());
+        param_id[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.send(&ParamValue {
+            param_id,
+            param_value: value,
+            param_type: MavParamType::Real32,
+            param_count: self.params.len() as u16,
+            param_index: index as u16,
+        });
+    }
+
+    /// Answers commands and parameter requests addressed to the vehicle.
+    fn handle(&mut self, msg: &Protocol) {
+        let system_id = self.component.system_id;
+        let component_id = self.component.component_id;
+        let is_target = |target_system: u8, target_component: u8| {
+            (target_system == 0 || target_system == system_id)
+                && (target_component == 0 || target_component == component_id)
+        };
+
+        if let Some(command) = msg.decode::<CommandLong>() {
+            if !is_target(command.target_system, command.target_component) {
+                return;
+            }
+            let result = match command.command {
+                MavCmd::ComponentArmDisarm => {
+                    self.armed = command.param1 == 1.0;
+                    info!(
+                        "simulated vehicle {}",
+                        if self.armed { "armed" } else { "disarmed" }
+                    );
+                    MavResult::Accepted
+                }
+                MavCmd::SetMessageInterval => {
+                    let message_id = command.param1 as u32;
+                    // -1 stops the stream, 0 restores its default rate
+                    let rate = match command.param2 {
+                        interval if interval > 0.0 && interval.is_finite() => Ok(1e6 / interval),
+                        interval if interval == 0.0 => {
+                            Ok(default_rates().get(&message_id).copied().unwrap_or(0.0))
+                        }
+                        interval if interval == -1.0 => Ok(0.0),
+                        interval => Err(format!("invalid interval {interval} us")),
+                    };
+                    match rate.and_then(|rate| self.set_rate(message_id, rate)) {
+                        Ok(()) => MavResult::Accepted,
+                        Err(e) => {
+                            warn!("simulated vehicle denied message {message_id} interval: {e}");
+                            MavResult::Denied
+                        }
+                    }
+                }
+                _ => MavResult::Unsupported,
+            };
+            self.send(&CommandAck {
+                command: command.command,
+                result,
+                target_system: msg.mav_frame.system_id(),
+                target_component: msg.mav_frame.component_id(),
+                ..Default::default()
+            });
+        } else if let Some(request) = msg.decode::<ParamRequestList>() {
+            if !is_target(request.target_system, request.target_component) {
+                return;
+            }
+            for (index, (name, value)) in self.params.iter().enumerate() {
+                self.param_value(index, name, *value);
+            }
+        } else if let Some(request) = msg.decode::<ParamRequestRead>() {
+            if !is_target(request.target_system, request.target_component) {
+                return;
+            }
+            let found = if request.param_index >= 0 {
+                self.params
+                    .iter()
+                    .enumerate()
+                    .nth(request.param_index as usize)
+            } else {
+                let name = param_name(&request.param_id);
+                self.params
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (n, _))| **n == name)
+            };
+            if let Some((index, (name, value))) = found {
+                self.param_value(index, name, *value);
+            }
+        } else if let Some(set) = msg.decode::<ParamSet>() {
+            if !is_target(set.target_system, set.target_component) {
+                return;
+            }
+            let name = param_name(&set.param_id);
+            if let Some(value) = self.params.get_mut(&name) {
+                *value = set.param_value;
+            }
+            if let Some((index, (name, value))) = self
+                .params
+                .iter()
+                .enumerate()
+                .find(|(_, (n, _))| **n == name)
+            {
+                self.param_value(index, name, *value);
+            }
+        }
+    }
+}
+
+fn param_name(param_id: &[u8; 16]) -> String {
+    let len = param_id
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(param_id.len());
+    String::from_utf8_lossy(&param_id[..len]).into_owned()
+}
+
+/// Runs the simulated vehicle of `endpoint` (`sim:<system_id>`).
+pub async fn simulate(
+    endpoint: &str,
+    options: &SimOptions,
+    mut broadcast_channel: (Sender<Protocol>, Receiver<Protocol>),
+) -> io::Result<()> {
+    let system_id: u8 = endpoint[SIM_PREFIX.len()..].parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid simulated vehicle endpoint {endpoint} (expecting sim:<system_id>)"),
+        )
+    })?;
+
+    let mut params = BTreeMap::from([
+        ("SYSID_THISMAV".to_string(), system_id as f32),
+        ("BATT_CAPACITY".to_string(), 5200.0),
+        ("WPNAV_SPEED".to_string(), 500.0),
+    ]);
+    params.extend(options.params.clone());
+
+    let mut vehicle = SimVehicle {
+        endpoint,
+        // MAV_TYPE_QUADROTOR
+        component: Component::new(system_id, options.component_id, 2),
+        tx: &broadcast_channel.0,
+        boot: Instant::now(),
+        armed: false,
+        streams: BTreeMap::new(),
+        params,
+    };
+    for (message_id, rate) in &options.rates {
+        if let Err(e) = vehicle.set_rate(*message_id, *rate) {
+            warn!("not streaming message {message_id}: {e}");
+        }
+    }
+    info!("simulating vehicle {system_id}/{}", options.component_id);
+
+    let mut ticks = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => vehicle.tick(),
+            res = broadcast_channel.1.recv() => {
+                match res {
+                    Ok(msg) => {
+                        if msg.is_for(endpoint) {
+                            vehicle.handle(&msg);
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("simulated vehicle lagged behind broadcast channel ({n} messages skipped)");
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }
+}
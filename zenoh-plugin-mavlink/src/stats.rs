@@ -0,0 +1,238 @@
+//! Link quality statistics of the MAVLink connections.
+//!
+//! Each connection counts, per remote system/component, the frames received, the frames lost (from gaps in
+//! the MAVLink sequence numbers) and the frames of each message, as well as the invalid frames (CRC or parse
+//! errors) it read, and the latency of the frames it read until they are published on zenoh (or batched, see
+//! [`crate::batch`]). Every `stats_interval` seconds, a snapshot with rates over the interval is published as
+//! JSON on `@/<zenoh_id>/@mavlink/v2/stats`. The last snapshot is also in the admin space, under
+//! `@/<zenoh_id>/router/status/plugins/mavlink/stats` (or the bridge's equivalent).
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mavio::MavFrame;
+use serde::Serialize;
+use tracing::{debug, error, info};
+use zenoh::{bytes::Encoding, key_expr::format::keformat, Session};
+
+use crate::liveliness::ke_liveliness_stats;
+
+#[derive(Clone, Default)]
+struct LinkCounters {
+    received: u64,
+    lost: u64,
+    bytes: u64,
+    last_sequence: Option<u8>,
+    /// Frames received, by message id.
+    messages: BTreeMap<u32, u64>,
+}
+
+#[derive(Clone, Default)]
+struct ConnectionCounters {
+    /// Invalid frames (CRC or parse errors).
+    errors: u64,
+    bytes: u64,
+    /// Frames published on zenoh, with their total and maximum latency (in microseconds).
+    published: u64,
+    latency: u64,
+    max_latency: u64,
+    /// By system/component id.
+    links: BTreeMap<(u8, u8), LinkCounters>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LinkStats {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub received: u64,
+    pub lost: u64,
+    /// Ratio of lost frames since the connection started.
+    pub loss: f64,
+    pub bytes_per_second: f64,
+    /// Frames per second, by message id.
+    pub message_rates: BTreeMap<u32, f64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConnectionStats {
+    pub endpoint: String,
+    pub errors: u64,
+    pub bytes_per_second: f64,
+    /// Mean and maximum time (in milliseconds) from the reception of a frame to its publication on zenoh, over the
+    /// interval. `None` if no frame was published.
+    pub latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+    pub links: Vec<LinkStats>,
+}
+
+#[derive(Default)]
+struct Inner {
+    counters: BTreeMap<String, ConnectionCounters>,
+    /// Counters at the time of the last snapshot, to compute rates.
+    previous: BTreeMap<String, ConnectionCounters>,
+    previous_time: Option<Instant>,
+    snapshot: Vec<ConnectionStats>,
+}
+
+/// Statistics of every connection, shared between the connections, the publication task and the admin space.
+#[derive(Clone, Default)]
+pub struct Statistics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Statistics {
+    /// Counts a frame of `len` bytes received on `endpoint`.
+    pub fn record(&self, endpoint: &str, frame: &MavFrame, len: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let connection = inner.counters.entry(endpoint.to_string()).or_default();
+        connection.bytes += len as u64;
+
+        let link = connection
+            .links
+            .entry((frame.system_id(), frame.component_id()))
+            .or_default();
+        let sequence = frame.sequence();
+        if let Some(last) = link.last_sequence {
+            link.lost += sequence.wrapping_sub(last).wrapping_sub(1) as u64;
+        }
+        link.last_sequence = Some(sequence);
+        link.received += 1;
+        link.bytes += len as u64;
+        *link.messages.entry(frame.message_id()).or_default() += 1;
+    }
+
+    /// Records the `latency` of a frame read on `endpoint` and published on zenoh.
+    pub fn published(&self, endpoint: &str, latency: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let connection = inner.counters.entry(endpoint.to_string()).or_default();
+        let latency = latency.as_micros() as u64;
+        connection.published += 1;
+        connection.latency += latency;
+        connection.max_latency = connection.max_latency.max(latency);
+    }
+
+    /// Counts an invalid frame read on `endpoint`.
+    pub fn error(&self, endpoint: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .counters
+            .entry(endpoint.to_string())
+            .or_default()
+            .errors += 1;
+    }
+
+    /// Computes the statistics since the previous snapshot.
+    pub fn snapshot(&self) -> Vec<ConnectionStats> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = inner
+            .previous_time
+            .map(|t| now.duration_since(t))
+            .unwrap_or(Duration::ZERO)
+            .as_secs_f64();
+        let rate = |current: u64, previous: u64| {
+            if elapsed > 0.0 {
+                current.saturating_sub(previous) as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+
+        let snapshot: Vec<ConnectionStats> = inner
+            .counters
+            .iter()
+            .map(|(endpoint, connection)| {
+                let previous = inner.previous.get(endpoint).cloned().unwrap_or_default();
+                let links = connection
+                    .links
+                    .iter()
+                    .map(|(&(system_id, component_id), link)| {
+                        let previous_link = previous
+                            .links
+                            .get(&(system_id, component_id))
+                            .cloned()
+                            .unwrap_or_default();
+                        LinkStats {
+                            system_id,
+                            component_id,
+                            received: link.received,
+                            lost: link.lost,
+                            loss: link.lost as f64 / (link.received + link.lost) as f64,
+                            bytes_per_second: rate(link.bytes, previous_link.bytes),
+                            message_rates: link
+                                .messages
+                                .iter()
+                                .map(|(id, count)| {
+                                    let previous_count =
+                                        previous_link.messages.get(id).copied().unwrap_or(0);
+                                    (*id, rate(*count, previous_count))
+                                })
+                                .collect(),
+                        }
+                    })
+                    .collect();
+                let published = connection.published.saturating_sub(previous.published);
+                let latency = connection.latency.saturating_sub(previous.latency);
+                ConnectionStats {
+                    endpoint: endpoint.clone(),
+                    errors: connection.errors,
+                    bytes_per_second: rate(connection.bytes, previous.bytes),
+                    latency_ms: (published > 0).then(|| latency as f64 / published as f64 / 1000.0),
+                    max_latency_ms: (published > 0).then(|| connection.max_latency as f64 / 1000.0),
+                    links,
+                }
+            })
+            .collect();
+
+        // the maximum latency is over the interval
+        for connection in inner.counters.values_mut() {
+            connection.max_latency = 0;
+        }
+        inner.previous = inner.counters.clone();
+        inner.previous_time = Some(now);
+        inner.snapshot = snapshot.clone();
+        snapshot
+    }
+
+    /// The last snapshot, as JSON.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(&self.inner.lock().unwrap().snapshot).unwrap_or_default()
+    }
+
+    /// Periodically takes a snapshot and publishes it on zenoh.
+    pub async fn run(self, zsession: Arc<Session>, interval: Duration) {
+        let ke = keformat!(
+            ke_liveliness_stats::formatter(),
+            zenoh_id = zsession.zid().into_keyexpr()
+        )
+        .unwrap();
+        let publisher = match zsession.declare_publisher(ke.clone()).await {
+            Ok(publisher) => publisher,
+            Err(e) => {
+                error!("unable to declare statistics publisher on {ke}: {e}");
+                return;
+            }
+        };
+        info!("publishing connection statistics on {ke}");
+
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let snapshot = self.snapshot();
+            match serde_json::to_vec(&snapshot) {
+                Ok(json) => {
+                    let put = publisher.put(json).encoding(Encoding::APPLICATION_JSON);
+                    if let Err(e) = put.await {
+                        error!("failed to publish statistics: {e}");
+                    } else {
+                        debug!("published statistics on {ke}");
+                    }
+                }
+                Err(e) => error!("failed to serialize statistics: {e}"),
+            }
+        }
+    }
+}
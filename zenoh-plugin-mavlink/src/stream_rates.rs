@@ -0,0 +1,176 @@
+//! Requests telemetry streams from vehicles, so Zenoh consumers get data without a ground control station.
+//!
+//! When a vehicle heartbeat is first seen on a MAVLink connection, or seen again after
+//! [`HEARTBEAT_TIMEOUT`] (e.g. after a reconnection or a reboot), the configured rates are sent
+//! through that connection with `SET_MESSAGE_INTERVAL` and/or the legacy `REQUEST_DATA_STREAM`.
+//! When the configuration is reloaded, the rates are requested again on the next heartbeats.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use mavio::dialects::common::{
+    enums::MavCmd,
+    messages::{CommandLong, Heartbeat, RequestDataStream},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{select, sync::broadcast::error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::Config,
+    protocol::{Protocol, ZENOH_ORIGIN},
+    routing::{Router, MAV_AUTOPILOT_INVALID},
+};
+
+/// A vehicle not heard for this long is considered gone, its streams are requested again when it comes back.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Serialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StreamRates {
+    /// Message id => rate in Hz, requested with `SET_MESSAGE_INTERVAL` (a rate of 0 disables the message).
+    #[serde(default)]
+    pub messages: BTreeMap<u32, f32>,
+    /// `MAV_DATA_STREAM` id => rate in Hz, requested with `REQUEST_DATA_STREAM` (a rate of 0 stops the stream).
+    #[serde(default)]
+    pub data_streams: BTreeMap<u8, u16>,
+}
+
+impl StreamRates {
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.data_streams.is_empty()
+    }
+
+    /// Rates of `other` take precedence over the ones of `self`.
+    fn merge(&self, other: &StreamRates) -> StreamRates {
+        let mut merged = self.clone();
+        merged.messages.extend(other.messages.clone());
+        merged.data_streams.extend(other.data_streams.clone());
+        merged
+    }
+}
+
+pub(crate) struct StreamRateService {
+    router: Router,
+    /// Rates requested from every vehicle heard on a connection, by endpoint.
+    connections: HashMap<String, StreamRates>,
+    /// Rates requested from a vehicle, by system id. They take precedence over the connection ones.
+    vehicles: HashMap<u8, StreamRates>,
+    /// Last heartbeat of each vehicle, by endpoint and system/component id.
+    last_seen: HashMap<(String, u8, u8), Instant>,
+}
+
+impl StreamRateService {
+    pub fn new(router: Router) -> Self {
+        let config = router.config();
+        let mut service = Self {
+            router,
+            connections: HashMap::new(),
+            vehicles: HashMap::new(),
+            last_seen: HashMap::new(),
+        };
+        service.update(&config);
+        service
+    }
+
+    /// Takes the rates of `config`, to be requested on the next heartbeat of every vehicle.
+    fn update(&mut self, config: &Config) {
+        self.connections = config
+            .mavlink_connections
+            .iter()
+            .map(|c| (c.endpoint.clone(), c.stream_rates.clone()))
+            .collect();
+        self.vehicles = config.vehicle_stream_rates.clone();
+        self.last_seen.clear();
+    }
+
+    pub async fn run(mut self) {
+        let mut rx = self.router.subscribe();
+        let mut config = self.router.watch_config();
+        loop {
+            select! {
+                res = rx.recv() => match res {
+                    Ok(msg) => self.handle(&msg),
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("stream rates lagged behind broadcast channel ({n} messages skipped)");
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                Ok(()) = config.changed() => {
+                    let config = config.borrow_and_update().clone();
+                    self.update(&config);
+                    info!("stream rates reloaded");
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, msg: &Protocol) {
+        if msg.origin == ZENOH_ORIGIN {
+            return;
+        }
+        let Some(heartbeat) = msg.decode::<Heartbeat>() else {
+            return;
+        };
+        if heartbeat.autopilot as u8 == MAV_AUTOPILOT_INVALID {
+            return;
+        }
+
+        let system_id = msg.mav_frame.system_id();
+        let component_id = msg.mav_frame.component_id();
+        let now = Instant::now();
+        let previous = self
+            .last_seen
+            .insert((msg.origin.clone(), system_id, component_id), now);
+        if previous.is_some_and(|t| now.duration_since(t) < HEARTBEAT_TIMEOUT) {
+            return;
+        }
+
+        let rates = match (
+            self.connections.get(&msg.origin),
+            self.vehicles.get(&system_id),
+        ) {
+            (Some(connection), Some(vehicle)) => connection.merge(vehicle),
+            (Some(rates), None) | (None, Some(rates)) => rates.clone(),
+            (None, None) => return,
+        };
+        info!(
+            "requesting streams from {system_id}/{component_id} through {}",
+            msg.origin
+        );
+        self.request(&msg.origin, system_id, component_id, &rates);
+    }
+
+    fn request(&self, endpoint: &str, system_id: u8, component_id: u8, rates: &StreamRates) {
+        for (message_id, rate) in &rates.messages {
+            let interval_us = if *rate > 0.0 { 1e6 / rate } else { -1.0 };
+            let command = CommandLong {
+                target_system: system_id,
+                target_component: component_id,
+                command: MavCmd::SetMessageInterval,
+                confirmation: 0,
+                param1: *message_id as f32,
+                param2: interval_us,
+                ..Default::default()
+            };
+            if let Err(e) = self.router.send_to(endpoint, &command) {
+                error!("failed to request message {message_id} at {rate}Hz: {e}");
+            }
+        }
+
+        for (stream_id, rate) in &rates.data_streams {
+            let request = RequestDataStream {
+                target_system: system_id,
+                target_component: component_id,
+                req_stream_id: *stream_id,
+                req_message_rate: *rate,
+                start_stop: (*rate > 0) as u8,
+            };
+            if let Err(e) = self.router.send_to(endpoint, &request) {
+                error!("failed to request data stream {stream_id} at {rate}Hz: {e}");
+            }
+        }
+    }
+}
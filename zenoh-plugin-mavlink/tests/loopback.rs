@@ -0,0 +1,1142 @@
+//! Runs the plugin against an in-process Zenoh session and loopback MAVLink endpoints,
+//! checking where frames written to one side come out.
+
+use std::{
+    net::{TcpListener, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+
+use mavio::{
+    dialects::common::{
+        enums::{MavAutopilot, MavCmd, MavMissionType, MavResult, MavType},
+        messages::{CommandAck, CommandLong, Heartbeat, MissionAck, MissionClearAll},
+        Common,
+    },
+    protocol::MavLinkVersion,
+    MavFrame, Message,
+};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use zenoh::{bytes::Encoding, Session};
+use zenoh_plugin_mavlink::{
+    batch::split_batch,
+    component::Component,
+    config::Config,
+    encoding::{Format, JsonMessage},
+    health::Health,
+    protocol::{parse_raw_frame, raw_frame_len, Protocol},
+    replay::TlogReader,
+    stats::Statistics,
+    MAVLinkPluginRuntime,
+};
+
+/// Time given to the plugin to bind its endpoints and declare its Zenoh entities.
+const STARTUP: Duration = Duration::from_millis(500);
+/// Time to wait for a frame that should not arrive.
+const SILENCE: Duration = Duration::from_millis(500);
+/// Time to wait for a frame that should arrive.
+const DELIVERY: Duration = Duration::from_secs(5);
+const HEARTBEAT_ID: u32 = 0;
+const COMMAND_LONG_ID: u32 = 76;
+const MISSION_COUNT_ID: u32 = 44;
+const MISSION_CLEAR_ALL_ID: u32 = 45;
+const HEARTBEAT_CRC_EXTRA: u8 = 50;
+
+fn free_udp_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn free_tcp_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+async fn open_session() -> Arc<Session> {
+    let mut config = zenoh::Config::default();
+    config.insert_json5("mode", r#""peer""#).unwrap();
+    config
+        .insert_json5("scouting/multicast/enabled", "false")
+        .unwrap();
+    config.insert_json5("listen/endpoints", "[]").unwrap();
+    Arc::new(zenoh::open(config).await.unwrap())
+}
+
+/// Starts the plugin on `zsession` with the given (JSON) configuration.
+async fn start_plugin(
+    zsession: Arc<Session>,
+    config: serde_json::Value,
+) -> Arc<MAVLinkPluginRuntime> {
+    let config: Config = serde_json::from_value(config).unwrap();
+    let plugin =
+        MAVLinkPluginRuntime::new(zsession, config, Statistics::default(), Health::default())
+            .await
+            .unwrap();
+    let plugin = Arc::new(plugin);
+    tokio::spawn({
+        let plugin = plugin.clone();
+        async move { plugin.run().await }
+    });
+    tokio::time::sleep(STARTUP).await;
+    plugin
+}
+
+async fn connect_tcp(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("unable to connect to tcpin endpoint on port {port}");
+}
+
+/// Raw bytes of `message` sent by `system_id`/`component_id`.
+fn raw<M: Message>(system_id: u8, component_id: u8, message: &M) -> Vec<u8> {
+    let frame = Component::new(system_id, component_id, MavType::Quadrotor as u8)
+        .frame("test", message)
+        .unwrap();
+    Protocol::new("test", frame).raw_frame()
+}
+
+/// MAVLink (X.25) checksum of `bytes` followed by `crc_extra`.
+fn checksum(bytes: &[u8], crc_extra: u8) -> u16 {
+    bytes
+        .iter()
+        .chain([crc_extra].iter())
+        .fold(0xFFFF, |crc: u16, byte| {
+            let tmp = byte ^ crc as u8;
+            let tmp = (tmp ^ (tmp << 4)) as u16;
+            (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+        })
+}
+
+/// Signs the raw MAVLink v2 frame `raw` (of a message with `crc_extra`) with a dummy signature.
+fn signed(raw: &[u8], crc_extra: u8) -> Vec<u8> {
+    let mut bytes = raw[..raw.len() - 2].to_vec();
+    bytes[2] |= 0x01;
+    let checksum = checksum(&bytes[1..], crc_extra);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    // link id, timestamp and signature
+    bytes.extend_from_slice(&[7, 1, 2, 3, 4, 5, 6, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6]);
+    bytes
+}
+
+fn vehicle_heartbeat() -> Heartbeat {
+    Heartbeat {
+        type_: MavType::Quadrotor,
+        autopilot: MavAutopilot::Generic,
+        ..Default::default()
+    }
+}
+
+/// Reads every frame arriving on `stream` until it stays silent for [`SILENCE`].
+async fn read_frames(stream: &mut TcpStream) -> Vec<MavFrame> {
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 1024];
+    while let Ok(Ok(n)) = timeout(SILENCE, stream.read(&mut buf)).await {
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..n]);
+    }
+
+    let mut frames = Vec::new();
+    let mut remaining = &bytes[..];
+    while let Some(len) = raw_frame_len(remaining).filter(|len| *len <= remaining.len()) {
+        frames.push(parse_raw_frame(&remaining[..len]).unwrap());
+        remaining = &remaining[len..];
+    }
+    frames
+}
+
+async fn read_exact_tcp(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    timeout(DELIVERY, stream.read_exact(&mut buf))
+        .await
+        .expect("no frame received on tcp endpoint")
+        .unwrap();
+    buf
+}
+
+#[tokio::test]
+async fn signed_frames_are_serialized_with_their_signature() {
+    let signed = signed(&raw(1, 1, &vehicle_heartbeat()), HEARTBEAT_CRC_EXTRA);
+    assert_eq!(raw_frame_len(&signed), Some(signed.len()));
+    let msg = Protocol::new("test", parse_raw_frame(&signed).unwrap());
+    let frame = msg.raw_frame();
+    assert_eq!(frame, signed);
+    assert_eq!(msg.raw_frame_len(), signed.len());
+
+    // a tlog of signed frames stays in sync
+    let tlog = [
+        1u64.to_be_bytes().as_slice(),
+        &frame,
+        2u64.to_be_bytes().as_slice(),
+        &frame,
+    ]
+    .concat();
+    let mut records = TlogReader::new(&tlog[..]);
+    for expected in [1, 2] {
+        let (timestamp, record) = records.next_record().await.unwrap().unwrap();
+        assert_eq!(timestamp, expected);
+        assert_eq!(Protocol::new("test", record).raw_frame(), signed);
+    }
+    assert!(records.next_record().await.unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn udpin_frames_reach_other_connections_and_zenoh() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/v2/out")
+        .await
+        .unwrap();
+
+    let udp_port = free_udp_port();
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [
+                { "endpoint": format!("udpin:127.0.0.1:{udp_port}") },
+                { "endpoint": format!("tcpin:127.0.0.1:{tcp_port}") },
+            ],
+            "to_zenoh": true,
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    let frame = raw(1, 1, &vehicle_heartbeat());
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.send_to(&frame, ("127.0.0.1", udp_port)).unwrap();
+
+    assert_eq!(read_exact_tcp(&mut tcp, frame.len()).await, frame);
+    let sample = timeout(DELIVERY, subscriber.recv_async())
+        .await
+        .expect("no frame published on zenoh")
+        .unwrap();
+    assert_eq!(sample.payload().to_bytes().as_ref(), frame.as_slice());
+    assert_eq!(sample.encoding().to_string(), "application/mavlink;v2");
+    // the plugin accepts its own samples on the `in` key expression
+    assert_eq!(Format::of(sample.encoding()), Some(Format::Frames));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signed_frames_are_published_with_their_signature() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/v2/out")
+        .await
+        .unwrap();
+
+    let udp_port = free_udp_port();
+    let batched_port = free_udp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{udp_port}") }],
+            "to_zenoh": true,
+        }),
+    )
+    .await;
+    let batching = open_session().await;
+    start_plugin(
+        batching.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{batched_port}") }],
+            "to_zenoh": true,
+            "to_zenoh_batch": { "max_bytes": 1 },
+        }),
+    )
+    .await;
+
+    let frame = signed(&raw(1, 1, &vehicle_heartbeat()), HEARTBEAT_CRC_EXTRA);
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.send_to(&frame, ("127.0.0.1", udp_port)).unwrap();
+    let sample = timeout(DELIVERY, subscriber.recv_async())
+        .await
+        .expect("no frame published on zenoh")
+        .unwrap();
+    assert_eq!(sample.payload().to_bytes().as_ref(), frame.as_slice());
+    assert_eq!(sample.encoding().to_string(), "application/mavlink;signed");
+
+    // the sessions are not connected: batches are received on the second one
+    let subscriber = batching
+        .declare_subscriber("@/*/@mavlink/v2/out")
+        .await
+        .unwrap();
+    udp.send_to(&frame, ("127.0.0.1", batched_port)).unwrap();
+    let sample = timeout(DELIVERY, subscriber.recv_async())
+        .await
+        .expect("no batch published on zenoh")
+        .unwrap();
+    assert_eq!(sample.encoding().to_string(), "application/mavlink;batch");
+    assert_eq!(Format::of(sample.encoding()), Some(Format::Batch));
+    let batch = sample.payload().to_bytes();
+    assert_eq!(split_batch(&batch).unwrap(), [frame.as_slice()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn published_frames_latency_is_in_statistics() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/v2/stats")
+        .await
+        .unwrap();
+
+    let udp_port = free_udp_port();
+    let endpoint = format!("udpin:127.0.0.1:{udp_port}");
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": endpoint }],
+            "to_zenoh": true,
+            "stats_interval": 1.0,
+        }),
+    )
+    .await;
+
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.send_to(&raw(1, 1, &vehicle_heartbeat()), ("127.0.0.1", udp_port))
+        .unwrap();
+
+    // the first snapshots may predate the frame
+    for _ in 0..5 {
+        let sample = timeout(DELIVERY, subscriber.recv_async())
+            .await
+            .expect("no statistics published on zenoh")
+            .unwrap();
+        let stats: serde_json::Value =
+            serde_json::from_slice(&sample.payload().to_bytes()).unwrap();
+        let Some(connection) = stats
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["endpoint"] == endpoint && !c["latency_ms"].is_null())
+        else {
+            continue;
+        };
+        let latency = connection["latency_ms"].as_f64().unwrap();
+        assert!((0.0..1000.0).contains(&latency), "{connection}");
+        assert!(connection["max_latency_ms"].as_f64().unwrap() >= latency);
+        return;
+    }
+    panic!("no latency in the statistics");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pending_batches_are_published_when_the_plugin_stops() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/v2/out")
+        .await
+        .unwrap();
+
+    let udp_port = free_udp_port();
+    let config: Config = serde_json::from_value(json!({
+        "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{udp_port}") }],
+        "to_zenoh": true,
+        "to_zenoh_batch": { "window": 3600 },
+    }))
+    .unwrap();
+    let plugin = MAVLinkPluginRuntime::new(
+        zsession.clone(),
+        config,
+        Statistics::default(),
+        Health::default(),
+    )
+    .await
+    .unwrap();
+    let task = tokio::spawn(async move { plugin.run().await });
+    tokio::time::sleep(STARTUP).await;
+
+    let frame = raw(1, 1, &vehicle_heartbeat());
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.send_to(&frame, ("127.0.0.1", udp_port)).unwrap();
+    assert!(timeout(SILENCE, subscriber.recv_async()).await.is_err());
+
+    task.abort();
+    let sample = timeout(DELIVERY, subscriber.recv_async())
+        .await
+        .expect("pending batch not published")
+        .unwrap();
+    let batch = sample.payload().to_bytes();
+    assert_eq!(split_batch(&batch).unwrap(), [frame.as_slice()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn late_joiners_get_the_history() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/**")
+        .await
+        .unwrap();
+
+    let udp_port = free_udp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{udp_port}") }],
+            "to_zenoh": true,
+            "to_zenoh_history": { "0": 1 },
+            "stats_interval": 0,
+        }),
+    )
+    .await;
+
+    let heartbeat = |custom_mode| Heartbeat {
+        custom_mode,
+        ..vehicle_heartbeat()
+    };
+    let frames = [
+        raw(1, 1, &heartbeat(1)),
+        raw(1, 1, &heartbeat(2)),
+        raw(2, 1, &heartbeat(3)),
+        raw(1, 1, &CommandAck::default()),
+    ];
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for frame in &frames {
+        udp.send_to(frame, ("127.0.0.1", udp_port)).unwrap();
+        // every frame is only published once
+        let sample = timeout(DELIVERY, subscriber.recv_async())
+            .await
+            .expect("no frame published on zenoh")
+            .unwrap();
+        assert_eq!(sample.payload().to_bytes().as_ref(), frame.as_slice());
+    }
+    assert!(timeout(SILENCE, subscriber.recv_async()).await.is_err());
+
+    // the last heartbeat of each system
+    let replies = zsession.get("@/*/@mavlink/v2/out").await.unwrap();
+    let mut history = Vec::new();
+    while let Ok(reply) = replies.recv_async().await {
+        history.push(reply.result().unwrap().payload().to_bytes().to_vec());
+    }
+    assert_eq!(history, [frames[1].clone(), frames[2].clone()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn frames_are_not_echoed() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/v2/out")
+        .await
+        .unwrap();
+
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [
+                { "endpoint": format!("tcpin:127.0.0.1:{tcp_port}") },
+            ],
+            "to_zenoh": true,
+            "from_zenoh": true,
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    // a frame read from a connection is published on zenoh, but not written back to that connection
+    let frame = raw(1, 1, &vehicle_heartbeat());
+    tcp.write_all(&frame).await.unwrap();
+    let sample = timeout(DELIVERY, subscriber.recv_async())
+        .await
+        .expect("no frame published on zenoh")
+        .unwrap();
+    assert_eq!(sample.payload().to_bytes().as_ref(), frame.as_slice());
+    assert!(read_frames(&mut tcp).await.is_empty());
+
+    // a frame injected from zenoh is written to the connection, but not published back on zenoh
+    let injected = raw(2, 1, &vehicle_heartbeat());
+    zsession
+        .put(
+            format!("@/{}/@mavlink/v2/in", zsession.zid()),
+            injected.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(read_exact_tcp(&mut tcp, injected.len()).await, injected);
+    assert!(timeout(SILENCE, subscriber.recv_async()).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn zenoh_frames_are_injected_in_every_connection() {
+    let zsession = open_session().await;
+
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_read_timeout(Some(DELIVERY)).unwrap();
+    let udp_port = receiver.local_addr().unwrap().port();
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [
+                { "endpoint": format!("udpout:127.0.0.1:{udp_port}") },
+                { "endpoint": format!("tcpin:127.0.0.1:{tcp_port}") },
+            ],
+            "from_zenoh": true,
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    // several frames may be carried by a single sample
+    let first = raw(1, 1, &vehicle_heartbeat());
+    let second = raw(2, 1, &vehicle_heartbeat());
+    zsession
+        .put(
+            format!("@/{}/@mavlink/v2/in", zsession.zid()),
+            [first.clone(), second.clone()].concat(),
+        )
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 280];
+    for expected in [&first, &second] {
+        let n = receiver
+            .recv(&mut buf)
+            .expect("no frame on udpout endpoint");
+        assert_eq!(&buf[..n], expected.as_slice());
+    }
+    assert_eq!(
+        read_exact_tcp(&mut tcp, first.len() + second.len()).await,
+        [first, second].concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_messages_are_injected() {
+    let zsession = open_session().await;
+
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [
+                { "endpoint": format!("tcpin:127.0.0.1:{tcp_port}") },
+            ],
+            "from_zenoh": true,
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    let message = JsonMessage {
+        system_id: Some(42),
+        component_id: None,
+        message: Common::Heartbeat(vehicle_heartbeat()),
+    };
+    zsession
+        .put(
+            format!("@/{}/@mavlink/v2/in", zsession.zid()),
+            serde_json::to_vec(&message).unwrap(),
+        )
+        .encoding(Encoding::APPLICATION_JSON)
+        .await
+        .unwrap();
+
+    let frames = read_frames(&mut tcp).await;
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].system_id(), 42);
+    // the plugin's component id
+    assert_eq!(frames[0].component_id(), 190);
+    assert_eq!(frames[0].message_id(), HEARTBEAT_ID);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mavlink1_connections_get_mavlink1_heartbeats() {
+    let zsession = open_session().await;
+
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{
+                "endpoint": format!("tcpin:127.0.0.1:{tcp_port}"),
+                "mavlink_version": 1,
+                "heartbeat": true,
+            }],
+            "heartbeat_interval": 0.1,
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+
+    let frames = read_frames(&mut tcp).await;
+    assert!(frames
+        .iter()
+        .any(|frame| frame.message_id() == HEARTBEAT_ID));
+    assert!(frames
+        .iter()
+        .all(|frame| frame.version() == MavLinkVersion::V1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_are_only_written_to_the_target_connection() {
+    let zsession = open_session().await;
+
+    let udp_port = free_udp_port();
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [
+                { "endpoint": format!("udpin:127.0.0.1:{udp_port}") },
+                { "endpoint": format!("tcpin:127.0.0.1:{tcp_port}") },
+            ],
+            "from_zenoh_access": { "default": { "commands": [400] } },
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    // vehicle 1 is heard on the udpin connection
+    let vehicle = UdpSocket::bind("127.0.0.1:0").unwrap();
+    vehicle.set_read_timeout(Some(DELIVERY)).unwrap();
+    vehicle
+        .send_to(&raw(1, 1, &vehicle_heartbeat()), ("127.0.0.1", udp_port))
+        .unwrap();
+    assert_eq!(read_frames(&mut tcp).await.len(), 1);
+
+    // MAV_CMD_COMPONENT_ARM_DISARM, no retransmission
+    let _replies = zsession
+        .get(format!(
+            "@/{}/@mavlink/v2/cmd/1/1?timeout=200;retries=0",
+            zsession.zid()
+        ))
+        .payload(json!({ "command": 400, "params": [1.0] }).to_string())
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 280];
+    let n = vehicle
+        .recv(&mut buf)
+        .expect("no command on udpin endpoint");
+    let command = Protocol::new("test", parse_raw_frame(&buf[..n]).unwrap())
+        .decode::<CommandLong>()
+        .expect("not a COMMAND_LONG");
+    assert_eq!(command.target_system, 1);
+    assert_eq!(command.param1, 1.0);
+
+    assert!(read_frames(&mut tcp)
+        .await
+        .iter()
+        .all(|frame| frame.message_id() != COMMAND_LONG_ID));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_to_every_component_are_acked_by_any() {
+    let zsession = open_session().await;
+
+    let udp_port = free_udp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{udp_port}") }],
+        }),
+    )
+    .await;
+
+    let vehicle = UdpSocket::bind("127.0.0.1:0").unwrap();
+    vehicle.set_read_timeout(Some(DELIVERY)).unwrap();
+    vehicle
+        .send_to(&raw(1, 1, &vehicle_heartbeat()), ("127.0.0.1", udp_port))
+        .unwrap();
+    tokio::time::sleep(SILENCE).await;
+
+    // MAV_CMD_DO_SET_MODE to component 0
+    let replies = zsession
+        .get(format!(
+            "@/{}/@mavlink/v2/cmd/1/0?timeout=2000;retries=0",
+            zsession.zid()
+        ))
+        .payload(json!({ "command": 176, "params": [1.0, 4.0] }).to_string())
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 280];
+    let command = loop {
+        let n = vehicle
+            .recv(&mut buf)
+            .expect("no command on udpin endpoint");
+        let frame = parse_raw_frame(&buf[..n]).unwrap();
+        if let Some(command) = Protocol::new("test", frame).decode::<CommandLong>() {
+            break command;
+        }
+    };
+    assert_eq!(command.target_component, 0);
+
+    // the autopilot acks from its own component id
+    let ack = CommandAck {
+        command: MavCmd::DoSetMode,
+        result: MavResult::Accepted,
+        target_system: 255,
+        target_component: 190,
+        ..Default::default()
+    };
+    vehicle
+        .send_to(&raw(1, 1, &ack), ("127.0.0.1", udp_port))
+        .unwrap();
+
+    let reply = timeout(DELIVERY, replies.recv_async())
+        .await
+        .expect("no command reply")
+        .unwrap();
+    let reply: serde_json::Value =
+        serde_json::from_slice(&reply.result().unwrap().payload().to_bytes()).unwrap();
+    assert_eq!(reply["command"], 176);
+    assert_eq!(reply["result"], 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reloaded_connections_are_opened_filtered_and_closed() {
+    let zsession = open_session().await;
+
+    let first_port = free_tcp_port();
+    let second_port = free_tcp_port();
+    let first = json!({ "endpoint": format!("tcpin:127.0.0.1:{first_port}") });
+    let second = json!({
+        "endpoint": format!("tcpin:127.0.0.1:{second_port}"),
+        "filter": { "deny": [HEARTBEAT_ID] },
+    });
+    let plugin = start_plugin(
+        zsession.clone(),
+        json!({ "mavlink_connections": [first], "from_zenoh": true }),
+    )
+    .await;
+    let mut first_tcp = connect_tcp(first_port).await;
+
+    // adding a connection leaves the first one open
+    plugin.reload(
+        serde_json::from_value(json!({
+            "mavlink_connections": [first, second],
+            "from_zenoh": true,
+        }))
+        .unwrap(),
+    );
+    let mut second_tcp = connect_tcp(second_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    let heartbeat = raw(1, 1, &vehicle_heartbeat());
+    let command = raw(1, 1, &CommandLong::default());
+    zsession
+        .put(
+            format!("@/{}/@mavlink/v2/in", zsession.zid()),
+            [heartbeat.clone(), command.clone()].concat(),
+        )
+        .await
+        .unwrap();
+    let ids = |frames: Vec<MavFrame>| frames.iter().map(|f| f.message_id()).collect::<Vec<_>>();
+    assert_eq!(
+        ids(read_frames(&mut first_tcp).await),
+        [HEARTBEAT_ID, COMMAND_LONG_ID]
+    );
+    assert_eq!(ids(read_frames(&mut second_tcp).await), [COMMAND_LONG_ID]);
+
+    // removing a connection closes it
+    plugin.reload(
+        serde_json::from_value(json!({
+            "mavlink_connections": [second],
+            "from_zenoh": true,
+        }))
+        .unwrap(),
+    );
+    let mut buf = [0u8; 16];
+    let closed = timeout(DELIVERY, first_tcp.read(&mut buf))
+        .await
+        .expect("removed connection still open");
+    assert!(matches!(closed, Ok(0) | Err(_)));
+}
+
+/// Queries the health of the plugin's connections.
+async fn health(zsession: &Session) -> serde_json::Value {
+    let replies = zsession
+        .get(format!("@/{}/@mavlink/v2/health", zsession.zid()))
+        .await
+        .unwrap();
+    let reply = timeout(DELIVERY, replies.recv_async())
+        .await
+        .expect("no health reply")
+        .unwrap();
+    serde_json::from_slice(&reply.result().unwrap().payload().to_bytes()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_connections_are_unhealthy() {
+    let zsession = open_session().await;
+
+    let udp_port = free_udp_port();
+    let endpoint = format!("udpin:127.0.0.1:{udp_port}");
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": endpoint, "health_timeout": 1.0 }],
+        }),
+    )
+    .await;
+
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.send_to(&raw(1, 1, &vehicle_heartbeat()), ("127.0.0.1", udp_port))
+        .unwrap();
+    tokio::time::sleep(SILENCE).await;
+    let status = health(&zsession).await;
+    assert_eq!(status["healthy"], true, "{status}");
+    assert_eq!(status["connections"][0]["endpoint"], endpoint.as_str());
+    assert_eq!(status["connections"][0]["state"], "connected");
+
+    // no frame for longer than health_timeout
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let status = health(&zsession).await;
+    assert_eq!(status["healthy"], false, "{status}");
+    assert!(status["connections"][0]["problem"]
+        .as_str()
+        .unwrap()
+        .starts_with("no frame received"));
+
+    udp.send_to(&raw(1, 1, &vehicle_heartbeat()), ("127.0.0.1", udp_port))
+        .unwrap();
+    tokio::time::sleep(SILENCE).await;
+    assert_eq!(health(&zsession).await["healthy"], true);
+}
+
+fn arm(target_system: u8) -> CommandLong {
+    CommandLong {
+        target_system,
+        target_component: 1,
+        command: MavCmd::ComponentArmDisarm,
+        param1: 1.0,
+        ..Default::default()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn injected_commands_are_authorized() {
+    let zsession = open_session().await;
+
+    let tcp_port = free_tcp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [
+                { "endpoint": format!("tcpin:127.0.0.1:{tcp_port}") },
+            ],
+            "from_zenoh": true,
+            "from_zenoh_access": {
+                "systems": {
+                    "1": { "keys": ["@/*/@mavlink/v2/in/gcs"], "commands": [400] },
+                },
+            },
+        }),
+    )
+    .await;
+    let mut tcp = connect_tcp(tcp_port).await;
+    tokio::time::sleep(STARTUP).await;
+
+    let in_key = format!("@/{}/@mavlink/v2/in", zsession.zid());
+    let gcs_key = format!("{in_key}/gcs");
+    // key not allowed for system 1
+    zsession.put(&in_key, raw(255, 190, &arm(1))).await.unwrap();
+    // MAV_CMD_COMPONENT_ARM_DISARM not permitted for system 2
+    zsession
+        .put(&gcs_key, raw(255, 190, &arm(2)))
+        .await
+        .unwrap();
+    // accepted
+    zsession
+        .put(&gcs_key, raw(255, 190, &arm(1)))
+        .await
+        .unwrap();
+
+    let commands: Vec<CommandLong> = read_frames(&mut tcp)
+        .await
+        .into_iter()
+        .filter_map(|frame| Protocol::new("test", frame).decode::<CommandLong>())
+        .collect();
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].target_system, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mission_replies_to_other_transactions_are_ignored() {
+    let zsession = open_session().await;
+
+    let udp_port = free_udp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{udp_port}") }],
+        }),
+    )
+    .await;
+
+    let vehicle = UdpSocket::bind("127.0.0.1:0").unwrap();
+    vehicle.set_read_timeout(Some(DELIVERY)).unwrap();
+    vehicle
+        .send_to(&raw(1, 1, &vehicle_heartbeat()), ("127.0.0.1", udp_port))
+        .unwrap();
+    tokio::time::sleep(SILENCE).await;
+
+    let clear = format!(
+        "@/{}/@mavlink/v2/mission/1/1/clear?timeout=1000;retries=0",
+        zsession.zid()
+    );
+    for acked in [false, true] {
+        let replies = zsession.get(&clear).await.unwrap();
+        let mut buf = [0u8; 280];
+        loop {
+            let n = vehicle
+                .recv(&mut buf)
+                .expect("no mission clear on udpin endpoint");
+            let frame = parse_raw_frame(&buf[..n]).unwrap();
+            if Protocol::new("test", frame)
+                .decode::<MissionClearAll>()
+                .is_some()
+            {
+                break;
+            }
+        }
+
+        // acks to another ground station and to a geofence clear
+        let mut acks = vec![
+            MissionAck {
+                target_system: 254,
+                target_component: 190,
+                ..Default::default()
+            },
+            MissionAck {
+                target_system: 255,
+                target_component: 190,
+                mission_type: MavMissionType::Fence,
+                ..Default::default()
+            },
+        ];
+        if acked {
+            acks.push(MissionAck {
+                target_system: 255,
+                target_component: 190,
+                ..Default::default()
+            });
+        }
+        for ack in &acks {
+            vehicle
+                .send_to(&raw(1, 1, ack), ("127.0.0.1", udp_port))
+                .unwrap();
+        }
+
+        let reply = timeout(DELIVERY, replies.recv_async())
+            .await
+            .expect("no mission reply")
+            .unwrap();
+        assert_eq!(reply.result().is_ok(), acked);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn denied_mission_uploads_are_refused() {
+    let zsession = open_session().await;
+
+    let udp_port = free_udp_port();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": format!("udpin:127.0.0.1:{udp_port}") }],
+            "from_zenoh_access": {
+                "systems": { "2": { "sources": ["a0b23"] } },
+            },
+        }),
+    )
+    .await;
+
+    let vehicle = UdpSocket::bind("127.0.0.1:0").unwrap();
+    vehicle.set_read_timeout(Some(SILENCE)).unwrap();
+    for system_id in [1, 2] {
+        vehicle
+            .send_to(
+                &raw(system_id, 1, &vehicle_heartbeat()),
+                ("127.0.0.1", udp_port),
+            )
+            .unwrap();
+    }
+    tokio::time::sleep(SILENCE).await;
+
+    let plan = json!({
+        "fileType": "Plan",
+        "version": 1,
+        "groundStation": "test",
+        "mission": {
+            "items": [
+                {
+                    "type": "SimpleItem",
+                    "command": 16,
+                    "frame": 3,
+                    "params": [0, 0, 0, null, 47.4, 8.5, 50],
+                },
+                // MAV_CMD_DO_FLIGHTTERMINATION
+                { "type": "SimpleItem", "command": 185, "frame": 2, "params": [1, 0, 0, 0, 0, 0, 0] },
+            ],
+        },
+    });
+    let mission = format!("@/{}/@mavlink/v2/mission", zsession.zid());
+    let upload = zsession
+        .get(format!("{mission}/1/1/upload?retries=0"))
+        .payload(plan.to_string())
+        .await
+        .unwrap();
+    // queries carry no source
+    let clear = zsession
+        .get(format!("{mission}/2/1/clear?retries=0"))
+        .await
+        .unwrap();
+
+    for replies in [upload, clear] {
+        let reply = timeout(DELIVERY, replies.recv_async())
+            .await
+            .expect("no mission reply")
+            .unwrap();
+        let e = reply.result().err().expect("mission query not refused");
+        let e = e.payload().try_to_string().unwrap();
+        assert!(e.contains("not authorized"), "{e}");
+    }
+    // nothing reached the vehicles
+    let mut buf = [0u8; 280];
+    while let Ok(n) = vehicle.recv(&mut buf) {
+        let frame = parse_raw_frame(&buf[..n]).unwrap();
+        assert!(![MISSION_COUNT_ID, MISSION_CLEAR_ALL_ID].contains(&frame.message_id()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn simulated_vehicles_deny_invalid_message_intervals() {
+    let zsession = open_session().await;
+    let subscriber = zsession
+        .declare_subscriber("@/*/@mavlink/v2/out")
+        .await
+        .unwrap();
+    start_plugin(
+        zsession.clone(),
+        json!({
+            "mavlink_connections": [{ "endpoint": "sim:1" }],
+            "to_zenoh": true,
+            "from_zenoh": true,
+        }),
+    )
+    .await;
+
+    // an interval of 1e30 us
+    let interval = CommandLong {
+        target_system: 1,
+        target_component: 1,
+        command: MavCmd::SetMessageInterval,
+        param1: HEARTBEAT_ID as f32,
+        param2: 1e30,
+        ..Default::default()
+    };
+    zsession
+        .put(
+            format!("@/{}/@mavlink/v2/in", zsession.zid()),
+            raw(255, 190, &interval),
+        )
+        .await
+        .unwrap();
+
+    // denied, and the vehicle keeps streaming
+    let mut denied = false;
+    let mut heartbeats = 0;
+    while !(denied && heartbeats >= 2) {
+        let sample = timeout(DELIVERY, subscriber.recv_async())
+            .await
+            .expect("simulated vehicle stopped")
+            .unwrap();
+        let msg = Protocol::new(
+            "test",
+            parse_raw_frame(&sample.payload().to_bytes()).unwrap(),
+        );
+        if let Some(ack) = msg.decode::<CommandAck>() {
+            assert_eq!(ack.result, MavResult::Denied);
+            denied = true;
+        } else if denied && msg.mav_frame.message_id() == HEARTBEAT_ID {
+            heartbeats += 1;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_configurations_are_rejected() {
+    let zsession = open_session().await;
+    for (config, problem) in [
+        (json!({ "heartbeat_interval": 0 }), "heartbeat_interval"),
+        (json!({ "heartbeat_interval": 1e-12 }), "heartbeat_interval"),
+        (json!({ "mav_type": 200 }), "mav_type"),
+        (json!({ "stats_interval": -1 }), "stats_interval"),
+        (json!({ "stats_interval": 1e-12 }), "stats_interval"),
+        (json!({ "to_zenoh_batch": { "window": -1 } }), "window"),
+        (json!({ "to_zenoh_batch": { "window": 1e30 } }), "window"),
+        (
+            json!({
+                "mavlink_connections": [
+                    { "endpoint": "sim:1", "sim": { "rates": { "0": 1e-30 } } },
+                ],
+            }),
+            "invalid rate",
+        ),
+        (
+            json!({
+                "mavlink_connections": [
+                    { "endpoint": "replay:flight.tlog", "replay": { "speed": -1 } },
+                ],
+            }),
+            "invalid speed",
+        ),
+        (
+            json!({ "recorder": { "max_file_duration": -1 } }),
+            "max_file_duration",
+        ),
+        (
+            json!({ "recorder": { "max_file_duration": 1e30 } }),
+            "max_file_duration",
+        ),
+        (
+            json!({ "health": { "reconnect_delay": -1 } }),
+            "reconnect_delay",
+        ),
+        (json!({ "health": { "timeout": 1e30 } }), "timeout"),
+        (
+            json!({
+                "mavlink_connections": [
+                    { "endpoint": "udpin:127.0.0.1:0", "health_timeout": -1 },
+                ],
+            }),
+            "health_timeout",
+        ),
+    ] {
+        let config: Config = serde_json::from_value(config).unwrap();
+        let plugin = MAVLinkPluginRuntime::new(
+            zsession.clone(),
+            config,
+            Statistics::default(),
+            Health::default(),
+        )
+        .await;
+        let e = plugin.err().expect("invalid configuration accepted");
+        assert!(e.to_string().contains(problem), "{e}");
+    }
+}